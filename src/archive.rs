@@ -0,0 +1,359 @@
+use futures::channel::mpsc;
+use futures::sink::SinkExt;
+use futures::stream::{Stream, TryStreamExt};
+use hyper::body::Bytes;
+use hyper::Body;
+use mpart_async::server::{MultipartField, MultipartStream};
+use std::convert::TryFrom;
+
+use crate::util::{get_multipart_filename, make_io_error};
+
+pub const ARCHIVE_QUERY_PARAMETER_NAME: &str = "archive";
+
+const TAR_BLOCK_SIZE: usize = 512;
+
+// Archive into which all files of a multipart upload are put
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Tar,
+    Zip,
+}
+
+impl ArchiveFormat {
+    // Parse "?archive=..."
+    // NOTE: Zip is the default because tar needs Content-Length of each file, which browsers do not send
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "" | "zip" => Ok(ArchiveFormat::Zip),
+            "tar" => Ok(ArchiveFormat::Tar),
+            _ => Err(format!(
+                "[ERROR] Unknown archive format '{}'. (tar or zip)\n",
+                value
+            )),
+        }
+    }
+
+    // Archive asked by receiver's Accept
+    pub fn from_accept(accept: &http::HeaderValue) -> Option<Self> {
+        let accept = accept.to_str().ok()?;
+        for media_range in accept.split(',') {
+            match media_range.split(';').next().unwrap_or("").trim() {
+                "application/x-tar" => return Some(ArchiveFormat::Tar),
+                "application/zip" => return Some(ArchiveFormat::Zip),
+                _ => {}
+            }
+        }
+        return None;
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "application/x-tar",
+            ArchiveFormat::Zip => "application/zip",
+        }
+    }
+
+    pub fn content_disposition(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "attachment; filename=\"files.tar\"",
+            ArchiveFormat::Zip => "attachment; filename=\"files.zip\"",
+        }
+    }
+}
+
+type ArchiveSender = mpsc::Sender<Result<Bytes, std::io::Error>>;
+type Field<S> = MultipartField<S, hyper::Error>;
+
+// Build the archive of the files in the multipart stream while reading it
+// NOTE: The first file is read before responding so that tar without Content-Length is rejected
+pub async fn archive<S>(
+    format: ArchiveFormat,
+    mut multipart_stream: MultipartStream<S, hyper::Error>,
+) -> Result<Body, std::io::Error>
+where
+    S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin + Send + 'static,
+{
+    let first_file = next_file(&mut multipart_stream).await?;
+    if let (ArchiveFormat::Tar, Some((name, field))) = (format, &first_file) {
+        tar_file_size(name, field)?;
+    }
+    let (mut sender, receiver) = mpsc::channel::<Result<Bytes, std::io::Error>>(1);
+    tokio::spawn(async move {
+        let result = match format {
+            ArchiveFormat::Tar => write_tar(first_file, &mut multipart_stream, &mut sender).await,
+            ArchiveFormat::Zip => write_zip(first_file, &mut multipart_stream, &mut sender).await,
+        };
+        if let Err(err) = result {
+            let _ = sender.send(Err(err)).await;
+        }
+    });
+    return Ok(Body::wrap_stream(receiver));
+}
+
+// Relay the first part, failing when a file follows because it would be dropped without an archive
+pub fn first_part<S>(
+    first_field: Field<S>,
+    mut multipart_stream: MultipartStream<S, hyper::Error>,
+) -> Body
+where
+    S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin + Send + 'static,
+{
+    let (mut sender, receiver) = mpsc::channel::<Result<Bytes, std::io::Error>>(1);
+    tokio::spawn(async move {
+        let result = write_first_part(first_field, &mut multipart_stream, &mut sender).await;
+        if let Err(err) = result {
+            let _ = sender.send(Err(err)).await;
+        }
+    });
+    return Body::wrap_stream(receiver);
+}
+
+async fn write_first_part<S>(
+    mut first_field: Field<S>,
+    multipart_stream: &mut MultipartStream<S, hyper::Error>,
+    sender: &mut ArchiveSender,
+) -> Result<(), std::io::Error>
+where
+    S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin,
+{
+    let to_io_error = |err| std::io::Error::new(std::io::ErrorKind::Other, err);
+    while let Some(chunk) = first_field.try_next().await.map_err(to_io_error)? {
+        send(sender, chunk).await?;
+    }
+    if let Some((name, _)) = next_file(multipart_stream).await? {
+        return Err(make_io_error(format!(
+            "'{}' can not be sent without ?archive after the first part",
+            name
+        )));
+    }
+    return Ok(());
+}
+
+async fn send(sender: &mut ArchiveSender, bytes: Bytes) -> Result<(), std::io::Error> {
+    return sender
+        .send(Ok(bytes))
+        .await
+        .map_err(|_| make_io_error("receiver disconnected".to_owned()));
+}
+
+// Take the next part having a file, skipping the other fields
+async fn next_file<S>(
+    multipart_stream: &mut MultipartStream<S, hyper::Error>,
+) -> Result<Option<(String, Field<S>)>, std::io::Error>
+where
+    S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin,
+{
+    let to_io_error = |err| std::io::Error::new(std::io::ErrorKind::Other, err);
+    while let Some(mut field) = multipart_stream.try_next().await.map_err(to_io_error)? {
+        // NOTE: A browser sends a file input without files as an empty file name
+        // NOTE: Directories in the file name are dropped not to be extracted outside
+        let filename = field
+            .headers()
+            .get("content-disposition")
+            .and_then(get_multipart_filename);
+        let name = filename
+            .as_deref()
+            .and_then(|filename| filename.rsplit(['/', '\\']).next())
+            .filter(|name| !name.is_empty() && *name != "." && *name != "..")
+            .map(|name| name.to_string());
+        if let Some(name) = name {
+            return Ok(Some((name, field)));
+        }
+        // Read through the field to get the next one
+        while field.try_next().await.map_err(to_io_error)?.is_some() {}
+    }
+    return Ok(None);
+}
+
+// NOTE: Tar needs the size of a file before its content, so each file should have Content-Length
+fn tar_file_size<S>(name: &str, field: &Field<S>) -> Result<u64, std::io::Error>
+where
+    S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin,
+{
+    return field
+        .headers()
+        .get("content-length")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| {
+            make_io_error(format!(
+                "'{}' has no Content-Length for tar, use ?archive=zip instead",
+                name
+            ))
+        });
+}
+
+async fn write_tar<S>(
+    first_file: Option<(String, Field<S>)>,
+    multipart_stream: &mut MultipartStream<S, hyper::Error>,
+    sender: &mut ArchiveSender,
+) -> Result<(), std::io::Error>
+where
+    S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin,
+{
+    let to_io_error = |err| std::io::Error::new(std::io::ErrorKind::Other, err);
+    let mut file = first_file;
+    while let Some((name, mut field)) = file {
+        let size = tar_file_size(&name, &field)?;
+        send(sender, Bytes::from(tar_header(&name, size).to_vec())).await?;
+        let mut n_bytes: u64 = 0;
+        while let Some(chunk) = field.try_next().await.map_err(to_io_error)? {
+            n_bytes += chunk.len() as u64;
+            if n_bytes > size {
+                return Err(make_io_error(format!(
+                    "'{}' is longer than its Content-Length",
+                    name
+                )));
+            }
+            send(sender, chunk).await?;
+        }
+        if n_bytes < size {
+            return Err(make_io_error(format!(
+                "'{}' is shorter than its Content-Length",
+                name
+            )));
+        }
+        let padding = (TAR_BLOCK_SIZE - (size % TAR_BLOCK_SIZE as u64) as usize) % TAR_BLOCK_SIZE;
+        send(sender, Bytes::from(vec![0u8; padding])).await?;
+        file = next_file(multipart_stream).await?;
+    }
+    // End of archive
+    send(sender, Bytes::from(vec![0u8; TAR_BLOCK_SIZE * 2])).await?;
+    return Ok(());
+}
+
+// ustar header of a regular file
+fn tar_header(name: &str, size: u64) -> [u8; TAR_BLOCK_SIZE] {
+    let mut header = [0u8; TAR_BLOCK_SIZE];
+    let mut name_len = name.len().min(100);
+    while !name.is_char_boundary(name_len) {
+        name_len -= 1;
+    }
+    header[..name_len].copy_from_slice(&name.as_bytes()[..name_len]);
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    if size < 0o77777777777 {
+        header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+    } else {
+        // NOTE: GNU base-256 encoding for a file of 8 GiB or larger
+        header[124] = 0x80;
+        header[128..136].copy_from_slice(&size.to_be_bytes());
+    }
+    let mtime = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0);
+    header[136..148].copy_from_slice(format!("{:011o}\0", mtime).as_bytes());
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    // Checksum is calculated with its field filled with spaces
+    header[148..156].copy_from_slice(b"        ");
+    let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    return header;
+}
+
+struct ZipEntry {
+    name: String,
+    crc32: u32,
+    size: u32,
+    offset: u32,
+}
+
+// General purpose flags: sizes in data descriptor, UTF-8 name
+const ZIP_FLAGS: u16 = 0x0008 | 0x0800;
+const ZIP_VERSION: u16 = 20;
+// 1980-01-01 00:00:00 in MS-DOS format
+const ZIP_DOS_TIME: u16 = 0;
+const ZIP_DOS_DATE: u16 = (1 << 5) | 1;
+
+// NOTE: Files are stored without compression and their sizes are put after their contents
+async fn write_zip<S>(
+    first_file: Option<(String, Field<S>)>,
+    multipart_stream: &mut MultipartStream<S, hyper::Error>,
+    sender: &mut ArchiveSender,
+) -> Result<(), std::io::Error>
+where
+    S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin,
+{
+    let to_io_error = |err| std::io::Error::new(std::io::ErrorKind::Other, err);
+    let too_large = || make_io_error("too large for zip without ZIP64".to_owned());
+    let mut entries: Vec<ZipEntry> = Vec::new();
+    let mut offset: u64 = 0;
+    let mut file = first_file;
+    while let Some((name, mut field)) = file {
+        let entry_offset = u32::try_from(offset).map_err(|_| too_large())?;
+        let mut local_header = Vec::with_capacity(30 + name.len());
+        local_header.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        local_header.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+        local_header.extend_from_slice(&ZIP_FLAGS.to_le_bytes());
+        // Stored
+        local_header.extend_from_slice(&0u16.to_le_bytes());
+        local_header.extend_from_slice(&ZIP_DOS_TIME.to_le_bytes());
+        local_header.extend_from_slice(&ZIP_DOS_DATE.to_le_bytes());
+        // CRC-32, compressed size and uncompressed size in data descriptor
+        local_header.extend_from_slice(&[0u8; 12]);
+        local_header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        local_header.extend_from_slice(&0u16.to_le_bytes());
+        local_header.extend_from_slice(name.as_bytes());
+        offset += local_header.len() as u64;
+        send(sender, Bytes::from(local_header)).await?;
+        let mut hasher = crc32fast::Hasher::new();
+        let mut size: u64 = 0;
+        while let Some(chunk) = field.try_next().await.map_err(to_io_error)? {
+            hasher.update(&chunk);
+            size += chunk.len() as u64;
+            send(sender, chunk).await?;
+        }
+        let size = u32::try_from(size).map_err(|_| too_large())?;
+        let crc32 = hasher.finalize();
+        let mut data_descriptor = Vec::with_capacity(16);
+        data_descriptor.extend_from_slice(&0x08074b50u32.to_le_bytes());
+        data_descriptor.extend_from_slice(&crc32.to_le_bytes());
+        data_descriptor.extend_from_slice(&size.to_le_bytes());
+        data_descriptor.extend_from_slice(&size.to_le_bytes());
+        offset += size as u64 + data_descriptor.len() as u64;
+        send(sender, Bytes::from(data_descriptor)).await?;
+        entries.push(ZipEntry {
+            name,
+            crc32,
+            size,
+            offset: entry_offset,
+        });
+        file = next_file(multipart_stream).await?;
+    }
+    let central_directory_offset = u32::try_from(offset).map_err(|_| too_large())?;
+    let n_entries = u16::try_from(entries.len()).map_err(|_| too_large())?;
+    let mut central_directory = Vec::new();
+    for entry in &entries {
+        central_directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        // Version made by and needed to extract
+        central_directory.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+        central_directory.extend_from_slice(&ZIP_VERSION.to_le_bytes());
+        central_directory.extend_from_slice(&ZIP_FLAGS.to_le_bytes());
+        central_directory.extend_from_slice(&0u16.to_le_bytes());
+        central_directory.extend_from_slice(&ZIP_DOS_TIME.to_le_bytes());
+        central_directory.extend_from_slice(&ZIP_DOS_DATE.to_le_bytes());
+        central_directory.extend_from_slice(&entry.crc32.to_le_bytes());
+        central_directory.extend_from_slice(&entry.size.to_le_bytes());
+        central_directory.extend_from_slice(&entry.size.to_le_bytes());
+        central_directory.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+        // Extra field length, comment length, disk number, internal and external attributes
+        central_directory.extend_from_slice(&[0u8; 12]);
+        central_directory.extend_from_slice(&entry.offset.to_le_bytes());
+        central_directory.extend_from_slice(entry.name.as_bytes());
+    }
+    let central_directory_size = central_directory.len() as u32;
+    // End of central directory
+    central_directory.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    central_directory.extend_from_slice(&[0u8; 4]);
+    central_directory.extend_from_slice(&n_entries.to_le_bytes());
+    central_directory.extend_from_slice(&n_entries.to_le_bytes());
+    central_directory.extend_from_slice(&central_directory_size.to_le_bytes());
+    central_directory.extend_from_slice(&central_directory_offset.to_le_bytes());
+    central_directory.extend_from_slice(&0u16.to_le_bytes());
+    send(sender, Bytes::from(central_directory)).await?;
+    return Ok(());
+}
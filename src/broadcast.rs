@@ -0,0 +1,83 @@
+use futures::channel::mpsc;
+use futures::stream::StreamExt;
+use http::Response;
+use hyper::body::Bytes;
+use hyper::Body;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// Max bytes a receiver can fall behind the broadcast before it is dropped
+const MAX_LAG_BYTES: u64 = 4 * 1024 * 1024;
+
+struct BroadcastReceiver {
+    sender: mpsc::UnboundedSender<Result<Bytes, std::io::Error>>,
+    // Bytes sent but not yet taken by the receiver's response
+    n_lagging_bytes: Arc<AtomicU64>,
+}
+
+// Sender's body shared with receivers which join and leave at any time
+pub struct Broadcaster {
+    res_headers: http::HeaderMap,
+    receivers: Mutex<Vec<BroadcastReceiver>>,
+}
+
+impl Broadcaster {
+    pub fn new(res_headers: http::HeaderMap) -> Self {
+        Broadcaster {
+            res_headers,
+            receivers: Mutex::new(Vec::new()),
+        }
+    }
+
+    // Create a response which gets the broadcast from now on
+    pub fn join(&self) -> Response<Body> {
+        let (sender, receiver) = mpsc::unbounded::<Result<Bytes, std::io::Error>>();
+        let n_lagging_bytes = Arc::new(AtomicU64::new(0));
+        let body = {
+            let n_lagging_bytes = Arc::clone(&n_lagging_bytes);
+            Body::wrap_stream(receiver.inspect(move |chunk| {
+                if let Ok(chunk) = chunk {
+                    n_lagging_bytes.fetch_sub(chunk.len() as u64, Ordering::Relaxed);
+                }
+            }))
+        };
+        self.receivers.lock().unwrap().push(BroadcastReceiver {
+            sender,
+            n_lagging_bytes,
+        });
+        let mut res = Response::new(body);
+        *res.headers_mut() = self.res_headers.clone();
+        return res;
+    }
+
+    // Send a chunk to all receivers, dropping the ones which left or are too slow
+    pub fn send(&self, chunk: Bytes) {
+        self.receivers.lock().unwrap().retain(|receiver| {
+            if receiver.sender.is_closed() {
+                return false;
+            }
+            let n_lagging_bytes = receiver.n_lagging_bytes.load(Ordering::Relaxed);
+            if n_lagging_bytes + chunk.len() as u64 > MAX_LAG_BYTES {
+                let _ = receiver.sender.unbounded_send(Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "too slow to follow the broadcast",
+                )));
+                return false;
+            }
+            receiver
+                .n_lagging_bytes
+                .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            return receiver.sender.unbounded_send(Ok(chunk.clone())).is_ok();
+        });
+    }
+
+    // Abort all receivers' responses
+    pub fn abort(&self, message: &str) {
+        for receiver in self.receivers.lock().unwrap().drain(..) {
+            let _ = receiver.sender.unbounded_send(Err(std::io::Error::new(
+                std::io::ErrorKind::ConnectionAborted,
+                message.to_string(),
+            )));
+        }
+    }
+}
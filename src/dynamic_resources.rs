@@ -194,8 +194,8 @@ pub fn no_script_html(query_params: &HashMap<String, String>) -> String {
     } else {
         std::format!(
             // language=html
-            r#"<h3>Step 2: Choose a file</h3>
-    <input type="file" name="input_file" {disabled}>"#,
+            r#"<h3>Step 2: Choose files</h3>
+    <input type="file" name="input_file" multiple {disabled}>"#,
             disabled = if path.is_empty() { "disabled" } else { "" },
         )
     };
@@ -248,10 +248,16 @@ pub fn no_script_html(query_params: &HashMap<String, String>) -> String {
         file_mode_input_checked = if mode == file_mode { "checked" } else { "" },
         text_mode_input_checked = if mode == text_mode { "checked" } else { "" },
         escaped_path = escaped_path,
+        // NOTE: Files are sent in zip so that none of multiple files is dropped
         post_action = if path.is_empty() {
             "".to_string()
-        } else {
+        } else if mode == text_mode {
             std::format!(r#"action="{escaped_path}""#, escaped_path = escaped_path)
+        } else {
+            std::format!(
+                r#"action="{escaped_path}?archive""#,
+                escaped_path = escaped_path
+            )
         },
         text_or_file_input = text_or_file_input,
         disabled = if path.is_empty() { "disabled" } else { "" },
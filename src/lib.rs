@@ -1,5 +1,21 @@
+// NOTE: Explicit returns and std::io::Error::new() are kept for readability and older toolchains
+#![allow(
+    clippy::needless_return,
+    clippy::io_other_error,
+    clippy::new_without_default
+)]
+
+mod archive;
+mod broadcast;
 pub mod dynamic_resources;
 mod macros;
+mod merge;
 pub mod piping_server;
+mod read_ahead;
+mod relay;
 pub mod req_res_handler;
+mod rpc;
+mod sender_event;
+mod storage;
 pub mod util;
+pub mod websocket;
@@ -7,7 +7,7 @@ use std::sync::{Arc, RwLock};
 use tokio::net::TcpListener;
 use tokio_rustls::TlsAcceptor;
 
-use piping_server::piping_server::PipingServer;
+use piping_server::piping_server::{PipingServer, PipingServerConfig};
 use piping_server::req_res_handler::req_res_handler;
 use piping_server::util;
 
@@ -35,6 +35,36 @@ struct Args {
     /// Private key path
     #[clap(long)]
     key_path: Option<String>,
+    /// Bytes kept per transfer for receivers resuming with Range (0: disabled)
+    #[clap(long, value_name = "BYTES", default_value = "0")]
+    replay_window_size: usize,
+    /// Seconds for a disconnected receiver to resume with Range
+    #[clap(long, value_name = "SECONDS", default_value = "60")]
+    receiver_resume_grace_period: u64,
+    /// Seconds for receivers to wait for an interrupted sender sending by Content-Range to resume
+    #[clap(long, value_name = "SECONDS", default_value = "60")]
+    sender_resume_timeout: u64,
+    /// Seconds for a sender to wait for receivers (default: unlimited)
+    #[clap(long, value_name = "SECONDS")]
+    sender_wait_timeout: Option<u64>,
+    /// Seconds for a receiver to wait for a sender (default: unlimited)
+    #[clap(long, value_name = "SECONDS")]
+    receiver_wait_timeout: Option<u64>,
+    /// Directory to store bodies sent with ?mailbox (default: disabled)
+    #[clap(long, value_name = "DIR")]
+    mailbox_dir: Option<std::path::PathBuf>,
+    /// Seconds to keep a body sent with ?mailbox
+    #[clap(long, value_name = "SECONDS", default_value = "86400")]
+    mailbox_ttl: u64,
+    /// Max bytes of a body sent with ?mailbox
+    #[clap(long, value_name = "BYTES", default_value = "1073741824")]
+    mailbox_max_size: u64,
+    /// Bytes read ahead from a sender per transfer not to wait for receivers (0: disabled)
+    #[clap(long, value_name = "BYTES", default_value = "0")]
+    read_ahead_buffer_size: u64,
+    /// Max bytes read ahead across all transfers, reported by /stats
+    #[clap(long, value_name = "BYTES", default_value = "268435456")]
+    max_read_ahead_memory: u64,
 }
 
 #[tokio::main]
@@ -45,7 +75,22 @@ async fn main() -> std::io::Result<()> {
     let mut tcp: TcpListener;
     let tls_cfg_rwlock_arc: Arc<RwLock<Arc<rustls::ServerConfig>>>;
 
-    let piping_server = &PipingServer::new();
+    let piping_server = &PipingServer::with_config(PipingServerConfig {
+        replay_window_size: args.replay_window_size,
+        receiver_resume_grace_period: std::time::Duration::from_secs(
+            args.receiver_resume_grace_period,
+        ),
+        sender_resume_timeout: std::time::Duration::from_secs(args.sender_resume_timeout),
+        sender_wait_timeout: args.sender_wait_timeout.map(std::time::Duration::from_secs),
+        receiver_wait_timeout: args
+            .receiver_wait_timeout
+            .map(std::time::Duration::from_secs),
+        mailbox_dir: args.mailbox_dir,
+        mailbox_ttl: std::time::Duration::from_secs(args.mailbox_ttl),
+        mailbox_max_size: args.mailbox_max_size,
+        read_ahead_buffer_size: args.read_ahead_buffer_size,
+        max_read_ahead_memory: args.max_read_ahead_memory,
+    });
 
     // Set default log level
     env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
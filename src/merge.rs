@@ -0,0 +1,179 @@
+use futures::channel::mpsc;
+use futures::sink::SinkExt;
+use futures::stream::{Stream, StreamExt};
+use hyper::body::Bytes;
+use hyper::Body;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+pub const MERGE_QUERY_PARAMETER_NAME: &str = "merge";
+
+// How chunks from senders are put into the receiver's body
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeFraming {
+    // Chunks as they are, where senders are merged one after another
+    Raw,
+    // Each chunk prefixed with the sender's ID in 8-byte and its length in 4-byte big endian
+    LengthPrefixed,
+    // Each chunk in a JSON line with the sender's ID, preceded by the sender's headers
+    Ndjson,
+}
+
+impl MergeFraming {
+    // Parse "?merge=..."
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "" | "raw" => Ok(MergeFraming::Raw),
+            "length" => Ok(MergeFraming::LengthPrefixed),
+            "ndjson" => Ok(MergeFraming::Ndjson),
+            _ => Err(format!(
+                "[ERROR] Unknown merge framing '{}'. (raw, length or ndjson)\n",
+                value
+            )),
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            MergeFraming::Raw | MergeFraming::LengthPrefixed => "application/octet-stream",
+            MergeFraming::Ndjson => "application/x-ndjson",
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum MergeEnvelope<'a> {
+    Start {
+        sender_id: u64,
+        content_type: Option<&'a str>,
+        x_piping: &'a [String],
+    },
+    Data {
+        sender_id: u64,
+        // Base64-encoded chunk
+        data: String,
+    },
+    End {
+        sender_id: u64,
+    },
+    Error {
+        sender_id: u64,
+        message: &'a str,
+    },
+}
+
+impl MergeEnvelope<'_> {
+    fn to_line(&self) -> Bytes {
+        Bytes::from(format!("{}\n", serde_json::to_string(self).unwrap()))
+    }
+}
+
+// Metadata of a sender to be merged
+pub struct MergeSource {
+    pub content_type: Option<String>,
+    pub x_piping: Vec<String>,
+}
+
+// Receiver's body into which senders connecting over time are merged
+pub struct Merger {
+    sender: mpsc::Sender<Result<Bytes, std::io::Error>>,
+    framing: MergeFraming,
+    last_sender_id: AtomicU64,
+    // Held by the sender being merged in raw framing, whose chunks can not be told from others'
+    raw_turn: tokio::sync::Mutex<()>,
+}
+
+impl Merger {
+    pub fn new(framing: MergeFraming) -> (Merger, Body) {
+        let (sender, receiver) = mpsc::channel::<Result<Bytes, std::io::Error>>(1);
+        let merger = Merger {
+            sender,
+            framing,
+            last_sender_id: AtomicU64::new(0),
+            raw_turn: tokio::sync::Mutex::new(()),
+        };
+        (merger, Body::wrap_stream(receiver))
+    }
+
+    // Put sender's chunks into the receiver's body until the sender finishes
+    pub async fn merge<S>(
+        &self,
+        source: MergeSource,
+        mut stream: S,
+        n_bytes: Arc<AtomicU64>,
+    ) -> Result<(), String>
+    where
+        S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin,
+    {
+        let sender_id = self.last_sender_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut sender = self.sender.clone();
+        let receiver_gone = |n_bytes: &AtomicU64| {
+            format!(
+                "receiver disconnected after {} bytes",
+                n_bytes.load(Ordering::Relaxed)
+            )
+        };
+        // NOTE: Other senders in raw framing wait until this sender finishes
+        let _raw_turn = if self.framing == MergeFraming::Raw {
+            Some(self.raw_turn.lock().await)
+        } else {
+            None
+        };
+        if self.framing == MergeFraming::Ndjson {
+            let start = MergeEnvelope::Start {
+                sender_id,
+                content_type: source.content_type.as_deref(),
+                x_piping: &source.x_piping,
+            };
+            sender
+                .send(Ok(start.to_line()))
+                .await
+                .map_err(|_| receiver_gone(&n_bytes))?;
+        }
+        while let Some(chunk) = stream.next().await {
+            let chunk = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    let message = format!("Failed to send: {}", err);
+                    if self.framing == MergeFraming::Ndjson {
+                        let error = MergeEnvelope::Error {
+                            sender_id,
+                            message: &message,
+                        };
+                        let _ = sender.send(Ok(error.to_line())).await;
+                    }
+                    return Err(message);
+                }
+            };
+            let frame = match self.framing {
+                MergeFraming::Raw => chunk.clone(),
+                MergeFraming::LengthPrefixed => {
+                    let mut frame = Vec::with_capacity(8 + 4 + chunk.len());
+                    frame.extend_from_slice(&sender_id.to_be_bytes());
+                    frame.extend_from_slice(&(chunk.len() as u32).to_be_bytes());
+                    frame.extend_from_slice(&chunk);
+                    Bytes::from(frame)
+                }
+                MergeFraming::Ndjson => MergeEnvelope::Data {
+                    sender_id,
+                    data: base64::encode(&chunk),
+                }
+                .to_line(),
+            };
+            sender
+                .send(Ok(frame))
+                .await
+                .map_err(|_| receiver_gone(&n_bytes))?;
+            n_bytes.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+        if self.framing == MergeFraming::Ndjson {
+            sender
+                .send(Ok(MergeEnvelope::End { sender_id }.to_line()))
+                .await
+                .map_err(|_| receiver_gone(&n_bytes))?;
+        }
+        return Ok(());
+    }
+}
@@ -7,15 +7,29 @@ use http::{Method, Request, Response};
 use hyper::body::Bytes;
 use hyper::Body;
 use serde_urlencoded;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 use url::Url;
 
+use crate::archive::{self, ArchiveFormat, ARCHIVE_QUERY_PARAMETER_NAME};
+use crate::broadcast::Broadcaster;
 use crate::dynamic_resources;
+use crate::merge::{MergeFraming, MergeSource, Merger, MERGE_QUERY_PARAMETER_NAME};
+use crate::read_ahead::{read_ahead, ReadAheadMemory};
+use crate::relay::{
+    receiver_body_channel, tee_stream, PathToRejoinRequestSender, RejoinRequest, Relay,
+    ReplayWindow, TeeEnd,
+};
+use crate::rpc;
+use crate::sender_event::{SenderEvent, SenderResFormat, FORMAT_QUERY_PARAMETER_NAME};
+use crate::storage::{Storage, StoreError};
 use crate::util::{
-    finish_detectable_stream, one_stream, FinishDetectableStream, HeaderValuesBuilder,
-    OptionHeaderBuilder,
+    attachment_content_disposition, drop_detectable_stream, finish_detectable_stream,
+    generate_token, get_multipart_filename, one_stream, parse_content_range, parse_range_start,
+    ContentRange, HeaderValuesBuilder, OptionHeaderBuilder,
 };
+use crate::websocket;
 
 pub mod reserved_paths {
     crate::with_values! {
@@ -23,45 +37,272 @@ pub mod reserved_paths {
         pub const NO_SCRIPT: &'static str = "/noscript";
         pub const VERSION: &'static str = "/version";
         pub const HELP: &'static str = "/help";
+        pub const STATS: &'static str = "/stats";
         pub const FAVICON_ICO: &'static str = "/favicon.ico";
         pub const ROBOTS_TXT: &'static str = "/robots.txt";
     }
 }
 
 pub const NO_SCRIPT_PATH_QUERY_PARAMETER_NAME: &str = "path";
+pub const N_RECEIVERS_QUERY_PARAMETER_NAME: &str = "n";
+pub const DUPLEX_QUERY_PARAMETER_NAME: &str = "duplex";
+pub const PEEK_QUERY_PARAMETER_NAME: &str = "peek";
+pub const QUEUE_QUERY_PARAMETER_NAME: &str = "queue";
+pub const BROADCAST_QUERY_PARAMETER_NAME: &str = "broadcast";
+pub const MAILBOX_QUERY_PARAMETER_NAME: &str = "mailbox";
+// Interval of progress events to sender
+const PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+// Time for a sender waiting for "100 Continue" to prove it has left, or it is regarded as connected
+const HELD_SENDER_PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+// Part of sender's response body, such as a message or events during the transfer
+type ResBodyStream = Pin<Box<dyn Stream<Item = Result<Bytes, std::convert::Infallible>> + Send>>;
+// Sender of the parts which are flattened into sender's response body
+type ResBodyStreamsSender = RwLock<mpsc::UnboundedSender<ResBodyStream>>;
 
 struct DataSender {
     req: Request<Body>,
-    res_body_streams_sender: RwLock<
-        mpsc::UnboundedSender<
-            Pin<Box<dyn Stream<Item = Result<Bytes, std::convert::Infallible>> + Send>>,
-        >,
-    >,
+    res_body_streams_sender: ResBodyStreamsSender,
+    n_receivers: u32,
+    res_format: SenderResFormat,
+    // Tell the watcher that this sender is no longer waiting
+    unparked_notifier: oneshot::Sender<()>,
+    // Response held back until transfer for "Expect: 100-continue"
+    held_res: Option<HeldRes>,
+    // Token required to cancel this sender by DELETE
+    cancel_token: String,
+}
+
+impl DataSender {
+    fn release_res(&mut self) {
+        if let Some(held_res) = self.held_res.take() {
+            let _ = held_res.release_notifier.send(());
+        }
+    }
+
+    fn send_waiting_events(&self, n_connected_receivers: usize) {
+        self.send_event(SenderEvent::Waiting {
+            n_receivers: self.n_receivers,
+        });
+        if n_connected_receivers > 0 {
+            self.send_event(SenderEvent::ReceiversConnected {
+                n_receivers: n_connected_receivers as u32,
+            });
+        }
+    }
+
+    fn send_event(&self, event: SenderEvent) {
+        let _ = self
+            .res_body_streams_sender
+            .write()
+            .unwrap()
+            .unbounded_send(
+                one_stream(Ok(self.res_format.render(self.req.uri().path(), &event))).boxed(),
+            );
+    }
+}
+
+// Handle of a task holding back a sender's response and body
+struct HeldRes {
+    release_notifier: oneshot::Sender<()>,
+    // Ask whether the sender is still connected
+    probe_sender: mpsc::UnboundedSender<oneshot::Sender<bool>>,
 }
 
 struct DataReceiver {
     res_sender: oneshot::Sender<Response<Body>>,
+    // Token required to cancel this receiver by DELETE (None: not cancellable)
+    cancel_token: Option<String>,
+    // Archive asked for a multipart upload by "?archive" or Accept
+    archive_format: Option<ArchiveFormat>,
+}
+
+// Receivers waiting on the same path until the specified number of receivers are connected
+struct DataReceivers {
+    n_receivers: u32,
+    receivers: Vec<DataReceiver>,
+    // Receivers waiting in FIFO order for the next sender after the current receivers
+    queued: VecDeque<DataReceiver>,
+}
+
+impl DataReceivers {
+    fn is_empty(&self) -> bool {
+        return self.receivers.is_empty() && self.queued.is_empty();
+    }
+
+    // Take the receivers to be paired and let queued ones take their places
+    fn take_receivers(&mut self) -> Vec<DataReceiver> {
+        let receivers = std::mem::take(&mut self.receivers);
+        self.fill_from_queue();
+        return receivers;
+    }
+
+    // Remove matching receivers including queued ones and return them
+    fn remove_receivers<F: FnMut(&DataReceiver) -> bool>(
+        &mut self,
+        mut matches: F,
+    ) -> Vec<DataReceiver> {
+        let (removed, receivers): (Vec<_>, Vec<_>) = std::mem::take(&mut self.receivers)
+            .into_iter()
+            .partition(&mut matches);
+        let (removed_queued, queued): (VecDeque<_>, VecDeque<_>) = std::mem::take(&mut self.queued)
+            .into_iter()
+            .partition(&mut matches);
+        self.receivers = receivers;
+        self.queued = queued;
+        self.fill_from_queue();
+        return removed.into_iter().chain(removed_queued).collect();
+    }
+
+    fn fill_from_queue(&mut self) {
+        while self.receivers.len() < self.n_receivers as usize {
+            match self.queued.pop_front() {
+                Some(data_receiver) => self.receivers.push(data_receiver),
+                None => break,
+            }
+        }
+    }
+}
+
+// A peer in duplex mode, which is both a sender and a receiver
+struct DuplexPeer {
+    req: Request<Body>,
+    data_receiver: DataReceiver,
+}
+
+// A peer waiting for the other one to splice CONNECT tunnels
+struct TunnelPeer {
+    on_upgrade: hyper::upgrade::OnUpgrade,
+    res_sender: oneshot::Sender<Response<Body>>,
+}
+
+// A client waiting for a worker in RPC mode
+struct RpcClient {
+    req: Request<Body>,
+    res_sender: oneshot::Sender<Response<Body>>,
+}
+
+// Clients and workers waiting on the same path in RPC mode
+#[derive(Default)]
+struct RpcPeers {
+    clients: VecDeque<RpcClient>,
+    workers: VecDeque<oneshot::Sender<Response<Body>>>,
+}
+
+// Receivers' bodies kept open until the sender resumes with Content-Range
+struct InterruptedTransfer {
+    relay: Relay,
+    // Tell the expiration timer that the transfer was taken
+    _resumed_notifier: oneshot::Sender<()>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PipingServerConfig {
+    // Max bytes kept per transfer for receivers resuming with Range (0: disabled)
+    pub replay_window_size: usize,
+    // How long a disconnected receiver can come back
+    pub receiver_resume_grace_period: std::time::Duration,
+    // How long receivers wait for an interrupted sender to resume
+    pub sender_resume_timeout: std::time::Duration,
+    // How long a sender waits for receivers (None: unlimited)
+    pub sender_wait_timeout: Option<std::time::Duration>,
+    // How long a receiver waits for a sender (None: unlimited)
+    pub receiver_wait_timeout: Option<std::time::Duration>,
+    // Directory to spool bodies of senders in mailbox mode (None: disabled)
+    pub mailbox_dir: Option<std::path::PathBuf>,
+    // How long a spooled body is kept
+    pub mailbox_ttl: std::time::Duration,
+    // Max bytes of a spooled body
+    pub mailbox_max_size: u64,
+    // Bytes read ahead from a sender per transfer not to wait for receivers (0: disabled)
+    pub read_ahead_buffer_size: u64,
+    // Max bytes read ahead across all transfers
+    pub max_read_ahead_memory: u64,
+}
+
+impl Default for PipingServerConfig {
+    fn default() -> Self {
+        PipingServerConfig {
+            replay_window_size: 0,
+            receiver_resume_grace_period: std::time::Duration::from_secs(60),
+            sender_resume_timeout: std::time::Duration::from_secs(60),
+            sender_wait_timeout: None,
+            receiver_wait_timeout: None,
+            mailbox_dir: None,
+            mailbox_ttl: std::time::Duration::from_secs(60 * 60 * 24),
+            mailbox_max_size: 1024 * 1024 * 1024,
+            read_ahead_buffer_size: 0,
+            max_read_ahead_memory: 256 * 1024 * 1024,
+        }
+    }
 }
 
 pub struct PipingServer {
-    path_to_sender: Arc<RwLock<HashMap<String, DataSender>>>,
-    path_to_receiver: Arc<RwLock<HashMap<String, DataReceiver>>>,
+    config: PipingServerConfig,
+    // Waiting senders in FIFO order. The front one is paired with the next receivers.
+    path_to_sender: Arc<RwLock<HashMap<String, VecDeque<DataSender>>>>,
+    path_to_receivers: Arc<RwLock<HashMap<String, DataReceivers>>>,
+    path_to_interrupted_transfer: Arc<RwLock<HashMap<String, InterruptedTransfer>>>,
+    path_to_rejoin_request_sender: PathToRejoinRequestSender,
+    path_to_duplex_peer: Arc<RwLock<HashMap<String, DuplexPeer>>>,
+    path_to_tunnel_peer: Arc<RwLock<HashMap<String, TunnelPeer>>>,
+    storage: Option<Arc<Storage>>,
+    read_ahead_memory: Arc<ReadAheadMemory>,
+    path_to_broadcaster: Arc<RwLock<HashMap<String, Arc<Broadcaster>>>>,
+    path_to_merger: Arc<RwLock<HashMap<String, Arc<Merger>>>>,
+    path_to_rpc_peers: Arc<RwLock<HashMap<String, RpcPeers>>>,
+    // Clients whose requests have been passed to workers
+    rpc_id_to_client_res_sender: Arc<RwLock<HashMap<String, oneshot::Sender<Response<Body>>>>>,
 }
 
 impl Clone for PipingServer {
     fn clone(&self) -> Self {
         PipingServer {
+            config: self.config.clone(),
             path_to_sender: Arc::clone(&self.path_to_sender),
-            path_to_receiver: Arc::clone(&self.path_to_receiver),
+            path_to_receivers: Arc::clone(&self.path_to_receivers),
+            path_to_interrupted_transfer: Arc::clone(&self.path_to_interrupted_transfer),
+            path_to_rejoin_request_sender: Arc::clone(&self.path_to_rejoin_request_sender),
+            path_to_duplex_peer: Arc::clone(&self.path_to_duplex_peer),
+            path_to_tunnel_peer: Arc::clone(&self.path_to_tunnel_peer),
+            storage: self.storage.clone(),
+            read_ahead_memory: Arc::clone(&self.read_ahead_memory),
+            path_to_broadcaster: Arc::clone(&self.path_to_broadcaster),
+            path_to_merger: Arc::clone(&self.path_to_merger),
+            path_to_rpc_peers: Arc::clone(&self.path_to_rpc_peers),
+            rpc_id_to_client_res_sender: Arc::clone(&self.rpc_id_to_client_res_sender),
         }
     }
 }
 
 impl PipingServer {
     pub fn new() -> Self {
+        PipingServer::with_config(PipingServerConfig::default())
+    }
+
+    pub fn with_config(config: PipingServerConfig) -> Self {
+        let storage = config.mailbox_dir.clone().map(|dir| {
+            Arc::new(Storage::new(
+                dir,
+                config.mailbox_ttl,
+                config.mailbox_max_size,
+            ))
+        });
+        let read_ahead_memory = Arc::new(ReadAheadMemory::new(config.max_read_ahead_memory));
         PipingServer {
+            config,
             path_to_sender: Arc::new(RwLock::new(HashMap::new())),
-            path_to_receiver: Arc::new(RwLock::new(HashMap::new())),
+            path_to_receivers: Arc::new(RwLock::new(HashMap::new())),
+            path_to_interrupted_transfer: Arc::new(RwLock::new(HashMap::new())),
+            path_to_rejoin_request_sender: Arc::new(RwLock::new(HashMap::new())),
+            path_to_duplex_peer: Arc::new(RwLock::new(HashMap::new())),
+            path_to_tunnel_peer: Arc::new(RwLock::new(HashMap::new())),
+            storage,
+            read_ahead_memory,
+            path_to_broadcaster: Arc::new(RwLock::new(HashMap::new())),
+            path_to_merger: Arc::new(RwLock::new(HashMap::new())),
+            path_to_rpc_peers: Arc::new(RwLock::new(HashMap::new())),
+            rpc_id_to_client_res_sender: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -72,7 +313,9 @@ impl PipingServer {
         res_sender: oneshot::Sender<Response<Body>>,
     ) -> impl std::future::Future<Output = ()> {
         let path_to_sender = Arc::clone(&self.path_to_sender);
-        let path_to_receiver = Arc::clone(&self.path_to_receiver);
+        let path_to_receivers = Arc::clone(&self.path_to_receivers);
+        let path_to_rejoin_request_sender = Arc::clone(&self.path_to_rejoin_request_sender);
+        let piping_server = self.clone();
         async move {
             let path = req.uri().path();
 
@@ -91,13 +334,7 @@ impl PipingServer {
                         return;
                     }
                     reserved_paths::NO_SCRIPT => {
-                        let query_params = match req.uri().query() {
-                            Some(query) => {
-                                serde_urlencoded::from_str::<HashMap<String, String>>(query)
-                                    .unwrap_or_else(|_| HashMap::new())
-                            }
-                            None => HashMap::new(),
-                        };
+                        let query_params = get_query_params(req.uri());
                         let html = dynamic_resources::no_script_html(&query_params);
                         let res = Response::builder()
                             .status(200)
@@ -148,6 +385,20 @@ impl PipingServer {
                         res_sender.send(res).unwrap();
                         return;
                     }
+                    reserved_paths::STATS => {
+                        let stats = serde_json::json!({
+                            "read_ahead_memory": piping_server.read_ahead_memory.n_bytes(),
+                            "max_read_ahead_memory": piping_server.read_ahead_memory.max_bytes(),
+                        });
+                        let res = Response::builder()
+                            .status(200)
+                            .header("Content-Type", "application/json")
+                            .header("Access-Control-Allow-Origin", "*")
+                            .body(Body::from(format!("{}\n", stats)))
+                            .unwrap();
+                        res_sender.send(res).unwrap();
+                        return;
+                    }
                     reserved_paths::FAVICON_ICO => {
                         let res = Response::builder().status(204).body(Body::empty()).unwrap();
                         res_sender.send(res).unwrap();
@@ -162,8 +413,47 @@ impl PipingServer {
                 }
             }
 
+            if websocket::is_websocket_upgrade(&req) {
+                let is_sender = matches!(
+                    get_query_params(req.uri()).get(websocket::ROLE_QUERY_PARAMETER_NAME),
+                    Some(role) if role == websocket::SENDER_ROLE
+                );
+                websocket::handle_websocket(piping_server, uses_https, req, is_sender, res_sender);
+                return;
+            }
+
+            if req.method() == Method::CONNECT {
+                // NOTE: The authority is used as the path when CONNECT has no path
+                let path = if path.is_empty() {
+                    req.uri()
+                        .authority()
+                        .map(|authority| format!("/{}", authority))
+                        .unwrap_or_default()
+                } else {
+                    path.to_string()
+                };
+                piping_server.tunnel(path, req, res_sender);
+                return;
+            }
+
+            if let Some(role) = get_query_params(req.uri()).get(rpc::RPC_QUERY_PARAMETER_NAME) {
+                match role.as_str() {
+                    rpc::WORKER_ROLE => piping_server.rpc_wait(path, res_sender),
+                    rpc::REPLY_ROLE => piping_server.rpc_reply(req, res_sender).await,
+                    _ => piping_server.rpc_call(path.to_string(), req, res_sender),
+                }
+                return;
+            }
+
             match req.method() {
+                &Method::HEAD => {
+                    piping_server.peek(path, false, res_sender);
+                }
                 &Method::GET => {
+                    if get_query_params(req.uri()).contains_key(PEEK_QUERY_PARAMETER_NAME) {
+                        piping_server.peek(path, true, res_sender);
+                        return;
+                    }
                     if let Some(value) = req.headers().get("service-worker") {
                         if value == http::HeaderValue::from_static("script") {
                             // Reject Service Worker registration
@@ -179,47 +469,226 @@ impl PipingServer {
                             return;
                         }
                     }
-                    let receiver_connected: bool =
-                        path_to_receiver.read().unwrap().contains_key(path);
-                    // If a receiver has been connected already
-                    if receiver_connected {
-                        let res = Response::builder()
-                            .status(400)
-                            .header("Content-Type", "text/plain")
-                            .header("Access-Control-Allow-Origin", "*")
-                            .body(Body::from(format!(
-                                "[ERROR] Another receiver has been connected on '{}'.\n",
-                                path
-                            )))
-                            .unwrap();
-                        res_sender.send(res).unwrap();
+                    // A mail spooled by a sender who has left
+                    if let Some(storage) = &piping_server.storage {
+                        if let Some(res) = storage.take(path) {
+                            log::info!("Mail delivered: '{}'", path);
+                            res_sender.send(res).unwrap();
+                            return;
+                        }
+                    }
+                    // A receiver coming back to an ongoing transfer
+                    if let Some(start) = req
+                        .headers()
+                        .get("range")
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(parse_range_start)
+                    {
+                        let rejoin_request_sender = path_to_rejoin_request_sender
+                            .read()
+                            .unwrap()
+                            .get(path)
+                            .cloned();
+                        if let Some(rejoin_request_sender) = rejoin_request_sender {
+                            let _ = rejoin_request_sender
+                                .unbounded_send(RejoinRequest { start, res_sender });
+                            return;
+                        }
+                    }
+                    if let Some(framing) =
+                        get_query_params(req.uri()).get(MERGE_QUERY_PARAMETER_NAME)
+                    {
+                        match MergeFraming::parse(framing) {
+                            Ok(framing) => piping_server.start_merge(path, framing, res_sender),
+                            Err(message) => {
+                                let res = Response::builder()
+                                    .status(400)
+                                    .header("Content-Type", "text/plain")
+                                    .header("Access-Control-Allow-Origin", "*")
+                                    .body(Body::from(message))
+                                    .unwrap();
+                                res_sender.send(res).unwrap();
+                            }
+                        }
                         return;
                     }
-                    let sender = path_to_sender.write().unwrap().remove(path);
-                    match sender {
-                        // If sender is found
-                        Some(data_sender) => {
-                            data_sender
-                                .res_body_streams_sender
-                                .write()
-                                .unwrap()
-                                .unbounded_send(
-                                    one_stream(Ok(Bytes::from(
-                                        "[INFO] A receiver was connected.\n",
+                    let n_receivers = match get_n_receivers(req.uri()) {
+                        Ok(n) => n,
+                        Err(message) => {
+                            let res = Response::builder()
+                                .status(400)
+                                .header("Content-Type", "text/plain")
+                                .header("Access-Control-Allow-Origin", "*")
+                                .body(Body::from(message))
+                                .unwrap();
+                            res_sender.send(res).unwrap();
+                            return;
+                        }
+                    };
+                    // Archive asked for a multipart upload
+                    let archive_format =
+                        match get_query_params(req.uri()).get(ARCHIVE_QUERY_PARAMETER_NAME) {
+                            Some(value) => match ArchiveFormat::parse(value) {
+                                Ok(archive_format) => Some(archive_format),
+                                Err(message) => {
+                                    let res = Response::builder()
+                                        .status(400)
+                                        .header("Content-Type", "text/plain")
+                                        .header("Access-Control-Allow-Origin", "*")
+                                        .body(Body::from(message))
+                                        .unwrap();
+                                    res_sender.send(res).unwrap();
+                                    return;
+                                }
+                            },
+                            None => req
+                                .headers()
+                                .get("accept")
+                                .and_then(ArchiveFormat::from_accept),
+                        };
+                    // NOTE: Both maps are locked in the same order as sender to pair atomically
+                    let established: Option<(DataSender, Vec<DataReceiver>)> = {
+                        let mut path_to_sender = path_to_sender.write().unwrap();
+                        let mut path_to_receivers = path_to_receivers.write().unwrap();
+                        // Join the ongoing broadcast
+                        if let Some(broadcaster) =
+                            piping_server.path_to_broadcaster.read().unwrap().get(path)
+                        {
+                            log::info!("Broadcast receiver joined: '{}'", path);
+                            res_sender.send(broadcaster.join()).unwrap();
+                            return;
+                        }
+                        // If a receiver is merging senders' bodies
+                        if piping_server
+                            .path_to_merger
+                            .read()
+                            .unwrap()
+                            .contains_key(path)
+                        {
+                            let res = Response::builder()
+                                .status(400)
+                                .header("Content-Type", "text/plain")
+                                .header("Access-Control-Allow-Origin", "*")
+                                .body(Body::from(format!(
+                                    "[ERROR] Another receiver has been connected on '{}'.\n",
+                                    path
+                                )))
+                                .unwrap();
+                            res_sender.send(res).unwrap();
+                            return;
+                        }
+                        if let Some(data_sender) =
+                            path_to_sender.get(path).and_then(VecDeque::front)
+                        {
+                            // If the number of receivers is different from the sender's one
+                            if data_sender.n_receivers != n_receivers {
+                                let res = Response::builder()
+                                    .status(400)
+                                    .header("Content-Type", "text/plain")
+                                    .header("Access-Control-Allow-Origin", "*")
+                                    .body(Body::from(format!(
+                                        "[ERROR] The number of receivers should be {} but {}.\n",
+                                        data_sender.n_receivers, n_receivers
                                     )))
-                                    .boxed(),
-                                )
+                                    .unwrap();
+                                res_sender.send(res).unwrap();
+                                return;
+                            }
+                        }
+                        let data_receivers = path_to_receivers
+                            .entry(path.to_string())
+                            .or_insert_with(|| DataReceivers {
+                                n_receivers,
+                                receivers: Vec::new(),
+                                queued: VecDeque::new(),
+                            });
+                        // If the number of receivers is different from the other receivers' one
+                        if data_receivers.n_receivers != n_receivers {
+                            let res = Response::builder()
+                                .status(400)
+                                .header("Content-Type", "text/plain")
+                                .header("Access-Control-Allow-Origin", "*")
+                                .body(Body::from(format!(
+                                    "[ERROR] The number of receivers should be {} but {}.\n",
+                                    data_receivers.n_receivers, n_receivers
+                                )))
                                 .unwrap();
-                            transfer(path.to_string(), data_sender, DataReceiver { res_sender })
-                                .await
+                            res_sender.send(res).unwrap();
+                            return;
+                        }
+                        let queues =
+                            get_query_params(req.uri()).contains_key(QUEUE_QUERY_PARAMETER_NAME);
+                        // If receivers have been connected already and queueing is not requested
+                        if data_receivers.receivers.len() == n_receivers as usize && !queues {
+                            let res = Response::builder()
+                                .status(400)
+                                .header("Content-Type", "text/plain")
+                                .header("Access-Control-Allow-Origin", "*")
+                                .body(Body::from(format!(
+                                    "[ERROR] Another receiver has been connected on '{}'.\n",
+                                    path
+                                )))
                                 .unwrap();
+                            res_sender.send(res).unwrap();
+                            return;
                         }
-                        // If sender is not found
+                        // NOTE: The response is forwarded to detect the receiver disconnecting while waiting
+                        let (parked_res_sender, parked_res_receiver) = oneshot::channel();
+                        let data_receiver = DataReceiver {
+                            res_sender: parked_res_sender,
+                            cancel_token: get_cancel_token(req.headers()),
+                            archive_format,
+                        };
+                        let queued = data_receivers.receivers.len() == n_receivers as usize;
+                        if queued {
+                            data_receivers.queued.push_back(data_receiver);
+                        } else {
+                            data_receivers.receivers.push(data_receiver);
+                        }
+                        {
+                            let piping_server = piping_server.clone();
+                            let path = path.to_string();
+                            tokio::spawn(async move {
+                                piping_server
+                                    .forward_waiting_receiver_response(
+                                        path,
+                                        parked_res_receiver,
+                                        res_sender,
+                                    )
+                                    .await;
+                            });
+                        }
+                        let all_receivers_connected =
+                            data_receivers.receivers.len() == n_receivers as usize;
+                        match path_to_sender.get(path).and_then(VecDeque::front) {
+                            // NOTE: A queued receiver waits for a later sender
+                            _ if queued => None,
+                            // If sender is found
+                            Some(data_sender) => {
+                                data_sender.send_event(SenderEvent::ReceiverConnected);
+                                if all_receivers_connected {
+                                    let data_sender =
+                                        remove_waiting_sender(&mut path_to_sender, path, 0, 0);
+                                    let data_receivers =
+                                        take_waiting_receivers(&mut path_to_receivers, path);
+                                    Some((data_sender, data_receivers))
+                                } else {
+                                    None
+                                }
+                            }
+                            // If sender is not found
+                            None => None,
+                        }
+                    };
+                    match established {
+                        Some((data_sender, data_receivers)) => {
+                            piping_server
+                                .transfer(path.to_string(), data_sender, data_receivers)
+                                .await;
+                        }
+                        // NOTE: A mail may have been stored after the check above
                         None => {
-                            path_to_receiver
-                                .write()
-                                .unwrap()
-                                .insert(path.to_string(), DataReceiver { res_sender });
+                            piping_server.deliver_mail_to_waiting_receiver(path);
                         }
                     }
                 }
@@ -238,91 +707,268 @@ impl PipingServer {
                         res_sender.send(res).unwrap();
                         return;
                     }
-                    // Notify that Content-Range is not supported
-                    // In the future, resumable upload using Content-Range might be supported
-                    // ref: https://github.com/httpwg/http-core/pull/653
-                    if req.headers().contains_key("content-range") {
-                        // Reject reserved path sending
+                    if let Some(Err(message)) = get_query_params(req.uri())
+                        .get(ARCHIVE_QUERY_PARAMETER_NAME)
+                        .map(|value| ArchiveFormat::parse(value))
+                    {
                         let res = Response::builder()
                             .status(400)
                             .header("Content-Type", "text/plain")
                             .header("Access-Control-Allow-Origin", "*")
-                            .body(Body::from(format!(
-                                "[ERROR] Content-Range is not supported for now in {}\n",
-                                req.method()
-                            )))
+                            .body(Body::from(message))
                             .unwrap();
                         res_sender.send(res).unwrap();
                         return;
                     }
-                    let sender_connected: bool = path_to_sender.read().unwrap().contains_key(path);
-                    // If a sender has been connected already
-                    if sender_connected {
-                        let res = Response::builder()
-                            .status(400)
-                            .header("Content-Type", "text/plain")
-                            .header("Access-Control-Allow-Origin", "*")
-                            .body(Body::from(format!(
-                                "[ERROR] Another sender has been connected on '{}'.\n",
-                                path
-                            )))
-                            .unwrap();
-                        res_sender.send(res).unwrap();
+                    if get_query_params(req.uri()).contains_key(DUPLEX_QUERY_PARAMETER_NAME) {
+                        piping_server
+                            .duplex(path.to_string(), req, res_sender)
+                            .await;
                         return;
                     }
-
-                    let (tx, rx) = mpsc::unbounded::<
-                        Pin<Box<dyn Stream<Item = Result<Bytes, std::convert::Infallible>> + Send>>,
-                    >();
-                    let body = hyper::body::Body::wrap_stream(rx.flatten());
-                    let sender_res = Response::builder()
-                        .header("Content-Type", "text/plain")
-                        .header("Access-Control-Allow-Origin", "*")
-                        .body(body)
-                        .unwrap();
-                    res_sender.send(sender_res).unwrap();
-
-                    let receiver = path_to_receiver.write().unwrap().remove(path);
-                    match receiver {
-                        // If receiver is found
-                        Some(data_receiver) => {
-                            tx.unbounded_send(
-                                one_stream(Ok(Bytes::from(
-                                    "[INFO] 1 receiver(s) has/have been connected.\n",
-                                )))
-                                .boxed(),
-                            )
-                            .unwrap();
-                            transfer(
+                    if get_query_params(req.uri()).contains_key(BROADCAST_QUERY_PARAMETER_NAME) {
+                        piping_server
+                            .broadcast(path.to_string(), req, res_sender)
+                            .await;
+                        return;
+                    }
+                    if get_query_params(req.uri()).contains_key(MAILBOX_QUERY_PARAMETER_NAME) {
+                        piping_server
+                            .store_mail(path.to_string(), req, res_sender)
+                            .await;
+                        return;
+                    }
+                    if let Some(content_range_value) = req.headers().get("content-range") {
+                        let content_range = match content_range_value
+                            .to_str()
+                            .ok()
+                            .and_then(parse_content_range)
+                        {
+                            Some(content_range) => content_range,
+                            None => {
+                                let res = Response::builder()
+                                    .status(400)
+                                    .header("Content-Type", "text/plain")
+                                    .header("Access-Control-Allow-Origin", "*")
+                                    .body(Body::from("[ERROR] Invalid Content-Range.\n"))
+                                    .unwrap();
+                                res_sender.send(res).unwrap();
+                                return;
+                            }
+                        };
+                        // Resume an interrupted transfer
+                        // NOTE: Content-Range starting at 0 is handled as a normal sender
+                        if content_range.start != 0 {
+                            piping_server.resume_transfer(
                                 path.to_string(),
-                                DataSender {
-                                    req,
-                                    res_body_streams_sender: RwLock::new(tx),
-                                },
-                                data_receiver,
-                            )
-                            .await
-                            .unwrap();
+                                req,
+                                content_range,
+                                res_sender,
+                            );
+                            return;
                         }
-                        // If receiver is not found
-                        None => {
-                            tx.unbounded_send(
-                                one_stream(Ok(Bytes::from(
-                                    "[INFO] Waiting for 1 receiver(s)...\n",
+                    }
+                    let n_receivers = match get_n_receivers(req.uri()) {
+                        Ok(n) => n,
+                        Err(message) => {
+                            let res = Response::builder()
+                                .status(400)
+                                .header("Content-Type", "text/plain")
+                                .header("Access-Control-Allow-Origin", "*")
+                                .body(Body::from(message))
+                                .unwrap();
+                            res_sender.send(res).unwrap();
+                            return;
+                        }
+                    };
+                    let path = path.to_string();
+                    piping_server.free_left_held_senders(&path).await;
+                    // NOTE: Both maps are locked in the same order as receiver to pair atomically
+                    let established: Option<(DataSender, Vec<DataReceiver>)> = {
+                        let mut path_to_sender = path_to_sender.write().unwrap();
+                        let mut path_to_receivers = path_to_receivers.write().unwrap();
+                        // If a broadcast is ongoing
+                        if piping_server
+                            .path_to_broadcaster
+                            .read()
+                            .unwrap()
+                            .contains_key(&path)
+                        {
+                            let res = Response::builder()
+                                .status(400)
+                                .header("Content-Type", "text/plain")
+                                .header("Access-Control-Allow-Origin", "*")
+                                .body(Body::from(format!(
+                                    "[ERROR] Another sender has been connected on '{}'.\n",
+                                    path
                                 )))
-                                .boxed(),
-                            )
+                                .unwrap();
+                            res_sender.send(res).unwrap();
+                            return;
+                        }
+                        let n_senders_ahead: usize = match path_to_sender.get(&path) {
+                            Some(data_senders) => {
+                                // If a sender has been connected already and queueing is not requested
+                                if !get_query_params(req.uri())
+                                    .contains_key(QUEUE_QUERY_PARAMETER_NAME)
+                                {
+                                    let res = Response::builder()
+                                        .status(400)
+                                        .header("Content-Type", "text/plain")
+                                        .header("Access-Control-Allow-Origin", "*")
+                                        .body(Body::from(format!(
+                                            "[ERROR] Another sender has been connected on '{}'.\n",
+                                            path
+                                        )))
+                                        .unwrap();
+                                    res_sender.send(res).unwrap();
+                                    return;
+                                }
+                                let front_n_receivers = data_senders.front().unwrap().n_receivers;
+                                // If the number of receivers is different from the queued senders' one
+                                if front_n_receivers != n_receivers {
+                                    let res = Response::builder()
+                                        .status(400)
+                                        .header("Content-Type", "text/plain")
+                                        .header("Access-Control-Allow-Origin", "*")
+                                        .body(Body::from(format!(
+                                            "[ERROR] The number of receivers should be {} but {}.\n",
+                                            front_n_receivers, n_receivers
+                                        )))
+                                        .unwrap();
+                                    res_sender.send(res).unwrap();
+                                    return;
+                                }
+                                data_senders.len()
+                            }
+                            None => 0,
+                        };
+                        let n_connected_receivers: usize = match path_to_receivers.get(&path) {
+                            Some(data_receivers) => {
+                                // If the number of receivers is different from the receivers' one
+                                if data_receivers.n_receivers != n_receivers {
+                                    let res = Response::builder()
+                                        .status(400)
+                                        .header("Content-Type", "text/plain")
+                                        .header("Access-Control-Allow-Origin", "*")
+                                        .body(Body::from(format!(
+                                            "[ERROR] The number of receivers should be {} but {}.\n",
+                                            data_receivers.n_receivers, n_receivers
+                                        )))
+                                        .unwrap();
+                                    res_sender.send(res).unwrap();
+                                    return;
+                                }
+                                data_receivers.receivers.len()
+                            }
+                            None => 0,
+                        };
+
+                        let (tx, rx) = mpsc::unbounded::<ResBodyStream>();
+                        let res_format = SenderResFormat::negotiate(
+                            get_query_params(req.uri()).get(FORMAT_QUERY_PARAMETER_NAME),
+                            req.headers().get("accept"),
+                        );
+                        let (sender_res_body, sender_res_body_drop_waiter) =
+                            drop_detectable_stream(rx.flatten());
+                        let body = hyper::body::Body::wrap_stream(sender_res_body);
+                        let cancel_token =
+                            get_cancel_token(req.headers()).unwrap_or_else(generate_token);
+                        let sender_res = Response::builder()
+                            .header("Content-Type", res_format.content_type())
+                            .header("X-Piping-Cancel-Token", cancel_token.as_str())
+                            .header("Access-Control-Allow-Origin", "*")
+                            .header("Access-Control-Expose-Headers", "X-Piping-Cancel-Token")
+                            .body(body)
                             .unwrap();
-                            path_to_sender.write().unwrap().insert(
-                                path.to_string(),
-                                DataSender {
-                                    req,
-                                    res_body_streams_sender: RwLock::new(tx),
-                                },
-                            );
+                        // NOTE: Sending the response before reading the body prevents "100 Continue"
+                        // NOTE: Such a sender can not see X-Piping-Cancel-Token until transfer, so it should send its own
+                        let (held_res, req) = if expects_continue(&req) {
+                            let (release_notifier, release_waiter) = oneshot::channel::<()>();
+                            let (probe_sender, probe_receiver) =
+                                mpsc::unbounded::<oneshot::Sender<bool>>();
+                            let (req_parts, req_body) = req.into_parts();
+                            let (held_body_sender, held_body) = Body::channel();
+                            tokio::spawn(hold_res(
+                                res_sender,
+                                sender_res,
+                                req_body,
+                                held_body_sender,
+                                release_waiter,
+                                probe_receiver,
+                            ));
+                            (
+                                Some(HeldRes {
+                                    release_notifier,
+                                    probe_sender,
+                                }),
+                                Request::from_parts(req_parts, held_body),
+                            )
+                        } else {
+                            res_sender.send(sender_res).unwrap();
+                            (None, req)
+                        };
+                        let (unparked_notifier, unparked_waiter) = oneshot::channel::<()>();
+                        let data_sender = DataSender {
+                            req,
+                            res_body_streams_sender: RwLock::new(tx),
+                            n_receivers,
+                            res_format,
+                            unparked_notifier,
+                            held_res,
+                            cancel_token,
+                        };
+                        // If a receiver is merging senders' bodies
+                        if let Some(merger) =
+                            piping_server.path_to_merger.read().unwrap().get(&path)
+                        {
+                            piping_server.merge(path.clone(), Arc::clone(merger), data_sender);
+                            return;
+                        }
+
+                        // If all receivers are found
+                        if n_senders_ahead == 0 && n_connected_receivers == n_receivers as usize {
+                            data_sender.send_event(SenderEvent::ReceiversConnected { n_receivers });
+                            let data_receivers =
+                                take_waiting_receivers(&mut path_to_receivers, &path);
+                            Some((data_sender, data_receivers))
+                        } else {
+                            if n_senders_ahead > 0 {
+                                data_sender.send_event(SenderEvent::Queued {
+                                    n_senders_ahead: n_senders_ahead as u32,
+                                });
+                            } else {
+                                data_sender.send_waiting_events(n_connected_receivers);
+                            }
+                            path_to_sender
+                                .entry(path.clone())
+                                .or_default()
+                                .push_back(data_sender);
+                            {
+                                let piping_server = piping_server.clone();
+                                let path = path.clone();
+                                tokio::spawn(async move {
+                                    piping_server
+                                        .watch_waiting_sender(
+                                            path,
+                                            unparked_waiter,
+                                            sender_res_body_drop_waiter,
+                                        )
+                                        .await;
+                                });
+                            }
+                            None
                         }
+                    };
+                    if let Some((data_sender, data_receivers)) = established {
+                        piping_server
+                            .transfer(path, data_sender, data_receivers)
+                            .await;
                     }
                 }
+                &Method::DELETE => {
+                    piping_server.cancel(path, req.headers(), res_sender);
+                }
                 &Method::OPTIONS => {
                     // Response for Preflight request
                     let res = Response::builder()
@@ -330,11 +976,11 @@ impl PipingServer {
                         .header("Access-Control-Allow-Origin", "*")
                         .header(
                             "Access-Control-Allow-Methods",
-                            "GET, HEAD, POST, PUT, OPTIONS",
+                            "GET, HEAD, POST, PUT, DELETE, OPTIONS",
                         )
                         .header(
                             "Access-Control-Allow-Headers",
-                            "Content-Type, Content-Disposition, X-Piping",
+                            "Content-Type, Content-Disposition, X-Piping, X-Piping-Cancel-Token",
                         )
                         .header("Access-Control-Max-Age", 86400)
                         .header("Content-Length", 0)
@@ -367,11 +1013,21 @@ struct TransferRequest {
 }
 
 #[inline(always)]
-fn raw_transfer_request(parts: &http::request::Parts, body: Body) -> TransferRequest {
+fn raw_transfer_request(headers: &http::HeaderMap, body: Body) -> TransferRequest {
+    let content_length = match headers.get("content-range") {
+        // Receivers get the whole content when sender sends it by ranges
+        Some(content_range_value) => content_range_value
+            .to_str()
+            .ok()
+            .and_then(parse_content_range)
+            .and_then(|content_range| content_range.complete_length)
+            .map(http::HeaderValue::from),
+        None => headers.get("content-length").cloned(),
+    };
     TransferRequest {
-        content_type: parts.headers.get("content-type").cloned(),
-        content_length: parts.headers.get("content-length").cloned(),
-        content_disposition: parts.headers.get("content-disposition").cloned(),
+        content_type: headers.get("content-type").cloned(),
+        content_length,
+        content_disposition: headers.get("content-disposition").cloned(),
         body,
     }
 }
@@ -379,10 +1035,11 @@ fn raw_transfer_request(parts: &http::request::Parts, body: Body) -> TransferReq
 async fn get_transfer_request(
     parts: &http::request::Parts,
     body: Body,
+    receivers_archive_format: Option<ArchiveFormat>,
 ) -> Result<TransferRequest, std::io::Error> {
     let content_type_option = parts.headers.get("content-type");
     if content_type_option.is_none() {
-        return Ok(raw_transfer_request(parts, body));
+        return Ok(raw_transfer_request(&parts.headers, body));
     }
     let content_type = content_type_option.unwrap();
     let mime_type_result: Result<mime::Mime, _> = match content_type.to_str() {
@@ -392,26 +1049,57 @@ async fn get_transfer_request(
         Err(err) => Err(std::io::Error::new(std::io::ErrorKind::Other, err)),
     };
     if mime_type_result.is_err() {
-        return Ok(raw_transfer_request(parts, body));
+        return Ok(raw_transfer_request(&parts.headers, body));
     }
     let mime_type = mime_type_result.unwrap();
     if mime_type.essence_str() != "multipart/form-data" {
-        return Ok(raw_transfer_request(parts, body));
+        return Ok(raw_transfer_request(&parts.headers, body));
     }
     let boundary = mime_type
         .get_param("boundary")
         .map(|b| b.to_string())
         .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "boundary not found"))?;
     let mut multipart_stream = mpart_async::server::MultipartStream::new(boundary, body);
+    // NOTE: Sender's "?archive" takes precedence over receivers'
+    let archive_format = get_query_params(&parts.uri)
+        .get(ARCHIVE_QUERY_PARAMETER_NAME)
+        .and_then(|value| ArchiveFormat::parse(value).ok())
+        .or(receivers_archive_format);
+    if let Some(archive_format) = archive_format {
+        return Ok(TransferRequest {
+            content_type: Some(http::HeaderValue::from_static(
+                archive_format.content_type(),
+            )),
+            content_length: None,
+            content_disposition: Some(http::HeaderValue::from_static(
+                archive_format.content_disposition(),
+            )),
+            body: archive::archive(archive_format, multipart_stream).await?,
+        });
+    }
 
-    while let Ok(Some(field)) = multipart_stream.try_next().await {
-        // NOTE: Only first one is transferred
+    if let Ok(Some(field)) = multipart_stream.try_next().await {
+        // NOTE: Only first one is transferred, and another file after it fails the transfer
         let headers = field.headers().clone();
+        // NOTE: "form-data" of a field is not for a response, so it is rewritten to "attachment"
+        let content_disposition = headers
+            .get("content-disposition")
+            .and_then(get_multipart_filename)
+            .filter(|filename| !filename.is_empty())
+            .map(|filename| attachment_content_disposition(&filename));
+        // A field without a file (e.g. text in /noscript) is in UTF-8 of the form
+        let content_type = match headers.get("content-type") {
+            Some(content_type) => Some(content_type.clone()),
+            None if content_disposition.is_none() => {
+                Some(http::HeaderValue::from_static("text/plain; charset=utf-8"))
+            }
+            None => None,
+        };
         return Ok(TransferRequest {
-            content_type: headers.get("content-type").cloned(),
+            content_type,
             content_length: headers.get("content-length").cloned(),
-            content_disposition: headers.get("content-disposition").cloned(),
-            body: Body::wrap_stream(field),
+            content_disposition,
+            body: archive::first_part(field, multipart_stream),
         });
     }
     return Err(std::io::Error::new(
@@ -420,65 +1108,1736 @@ async fn get_transfer_request(
     ));
 }
 
-async fn transfer(
-    path: String,
-    data_sender: DataSender,
-    data_receiver: DataReceiver,
-) -> Result<(), std::io::Error> {
-    let (data_sender_parts, data_sender_body) = data_sender.req.into_parts();
-    log::info!("Transfer start: '{}'", path);
-    // Extract transfer headers and body even when request is multipart
-    let transfer_request = get_transfer_request(&data_sender_parts, data_sender_body).await?;
-    // The finish_waiter will tell when the body is finished
-    let (finish_detectable_body, sender_req_body_finish_waiter) =
-        finish_detectable_stream(transfer_request.body);
-    // Create receiver's body
-    let receiver_res_body = Body::wrap_stream::<FinishDetectableStream<Body>, Bytes, hyper::Error>(
-        finish_detectable_body,
-    );
-    let x_piping = data_sender_parts.headers.get_all("x-piping");
-    let has_x_piping = data_sender_parts.headers.contains_key("x-piping");
-    // Create receiver's response
-    let receiver_res = Response::builder()
-        .option_header("Content-Type", transfer_request.content_type)
-        .option_header("Content-Length", transfer_request.content_length)
-        .option_header("Content-Disposition", transfer_request.content_disposition)
-        .header_values("X-Piping", x_piping.into_iter().cloned())
-        .header("Access-Control-Allow-Origin", "*")
-        .option_header(
-            "Access-Control-Expose-Headers",
-            if has_x_piping { Some("X-Piping") } else { None },
+impl PipingServer {
+    async fn transfer(
+        &self,
+        path: String,
+        mut data_sender: DataSender,
+        data_receivers: Vec<DataReceiver>,
+    ) {
+        log::info!("Transfer start: '{}'", path);
+        data_sender.release_res();
+        let relay_progress = match self
+            .start_relay(path.clone(), data_sender.req, data_receivers, true)
+            .await
+        {
+            Ok(relay_progress) => relay_progress,
+            Err(err) => {
+                log::info!("Transfer failed: '{}': {}", path, err);
+                let event = SenderEvent::Error {
+                    message: format!("Failed to send: {}", err),
+                };
+                let _ = data_sender
+                    .res_body_streams_sender
+                    .write()
+                    .unwrap()
+                    .unbounded_send(
+                        one_stream(Ok(data_sender.res_format.render(&path, &event))).boxed(),
+                    );
+                return;
+            }
+        };
+        send_sending_events(
+            &data_sender.res_body_streams_sender,
+            data_sender.res_format,
+            data_sender.n_receivers,
+            path,
+            relay_progress,
+        );
+    }
+
+    // Make a receiver into which senders connecting over time are merged
+    fn start_merge(
+        &self,
+        path: &str,
+        framing: MergeFraming,
+        res_sender: oneshot::Sender<Response<Body>>,
+    ) {
+        let (merger, body) = Merger::new(framing);
+        let merger = Arc::new(merger);
+        let (body, body_drop_waiter) = drop_detectable_stream(body);
+        let data_senders: VecDeque<DataSender> = {
+            let mut path_to_sender = self.path_to_sender.write().unwrap();
+            let path_to_receivers = self.path_to_receivers.read().unwrap();
+            let mut path_to_merger = self.path_to_merger.write().unwrap();
+            if path_to_receivers.contains_key(path)
+                || path_to_merger.contains_key(path)
+                || self.path_to_broadcaster.read().unwrap().contains_key(path)
+            {
+                let res = Response::builder()
+                    .status(400)
+                    .header("Content-Type", "text/plain")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(format!(
+                        "[ERROR] Another receiver has been connected on '{}'.\n",
+                        path
+                    )))
+                    .unwrap();
+                let _ = res_sender.send(res);
+                return;
+            }
+            path_to_merger.insert(path.to_string(), Arc::clone(&merger));
+            // Senders which have been waiting are merged first
+            path_to_sender.remove(path).unwrap_or_default()
+        };
+        log::info!("Merge start: '{}'", path);
+        let res = Response::builder()
+            .header("Content-Type", framing.content_type())
+            .header("Access-Control-Allow-Origin", "*")
+            .header("X-Robots-Tag", "none")
+            .body(Body::wrap_stream(body))
+            .unwrap();
+        let _ = res_sender.send(res);
+        for data_sender in data_senders {
+            self.merge(path.to_string(), Arc::clone(&merger), data_sender);
+        }
+        // Free the path when the receiver leaves
+        let path_to_merger = Arc::clone(&self.path_to_merger);
+        let path = path.to_string();
+        tokio::spawn(async move {
+            let _ = body_drop_waiter.await;
+            let mut path_to_merger = path_to_merger.write().unwrap();
+            if matches!(path_to_merger.get(&path), Some(m) if Arc::ptr_eq(m, &merger)) {
+                path_to_merger.remove(&path);
+            }
+            log::info!("Merge end: '{}'", path);
+        });
+    }
+
+    // Put the sender's body into the merging receiver's one
+    fn merge(&self, path: String, merger: Arc<Merger>, mut data_sender: DataSender) {
+        log::info!("Transfer start: '{}'", path);
+        data_sender.release_res();
+        let (finish_notifier, finish_waiter) = oneshot::channel::<Result<(), String>>();
+        let n_bytes = Arc::new(AtomicU64::new(0));
+        send_sending_events(
+            &data_sender.res_body_streams_sender,
+            data_sender.res_format,
+            1,
+            path,
+            RelayProgress {
+                finish_waiter,
+                n_bytes: Arc::clone(&n_bytes),
+            },
+        );
+        tokio::spawn(async move {
+            let (parts, body) = data_sender.req.into_parts();
+            let result = match get_transfer_request(&parts, body, None).await {
+                Ok(transfer_request) => {
+                    let source = MergeSource {
+                        content_type: transfer_request
+                            .content_type
+                            .and_then(|value| value.to_str().ok().map(|value| value.to_string())),
+                        x_piping: parts
+                            .headers
+                            .get_all("x-piping")
+                            .into_iter()
+                            .filter_map(|value| value.to_str().ok())
+                            .map(|value| value.to_string())
+                            .collect(),
+                    };
+                    merger.merge(source, transfer_request.body, n_bytes).await
+                }
+                Err(err) => Err(format!("Failed to send: {}", err)),
+            };
+            let _ = finish_notifier.send(result);
+        });
+    }
+
+    // Cancel the sender and receivers waiting on the path with the given cancel token
+    // NOTE: Peers parked in duplex, tunnel, RPC and merge modes are not cancellable
+    fn cancel(
+        &self,
+        path: &str,
+        headers: &http::HeaderMap,
+        res_sender: oneshot::Sender<Response<Body>>,
+    ) {
+        let cancel_token = match get_cancel_token(headers) {
+            Some(cancel_token) => cancel_token,
+            None => {
+                let res = Response::builder()
+                    .status(400)
+                    .header("Content-Type", "text/plain")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(
+                        "[ERROR] X-Piping-Cancel-Token header is required to cancel.\n",
+                    ))
+                    .unwrap();
+                let _ = res_sender.send(res);
+                return;
+            }
+        };
+        let mut path_to_sender = self.path_to_sender.write().unwrap();
+        let mut path_to_receivers = self.path_to_receivers.write().unwrap();
+        let cancelled_receivers: Vec<DataReceiver> = {
+            match path_to_receivers.get_mut(path) {
+                Some(data_receivers) => {
+                    let cancelled = data_receivers.remove_receivers(|data_receiver| {
+                        data_receiver.cancel_token.as_ref() == Some(&cancel_token)
+                    });
+                    if data_receivers.is_empty() {
+                        path_to_receivers.remove(path);
+                    }
+                    cancelled
+                }
+                None => Vec::new(),
+            }
+        };
+        let cancelled_sender: Option<DataSender> = path_to_sender
+            .get(path)
+            .and_then(|data_senders| {
+                data_senders
+                    .iter()
+                    .position(|data_sender| data_sender.cancel_token == cancel_token)
+            })
+            .map(|index| {
+                let n_connected_receivers = path_to_receivers
+                    .get(path)
+                    .map_or(0, |data_receivers| data_receivers.receivers.len());
+                remove_waiting_sender(&mut path_to_sender, path, index, n_connected_receivers)
+            });
+        drop(path_to_receivers);
+        drop(path_to_sender);
+        if cancelled_sender.is_none() && cancelled_receivers.is_empty() {
+            let res = Response::builder()
+                .status(404)
+                .header("Content-Type", "text/plain")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from(format!(
+                    "[ERROR] Nothing waiting on '{}' has the cancel token.\n",
+                    path
+                )))
+                .unwrap();
+            let _ = res_sender.send(res);
+            return;
+        }
+        let message = format!("Cancelled by DELETE on '{}'.", path);
+        if let Some(mut data_sender) = cancelled_sender {
+            log::info!("Waiting sender cancelled: '{}'", path);
+            data_sender.send_event(SenderEvent::Error {
+                message: message.clone(),
+            });
+            data_sender.release_res();
+        }
+        for data_receiver in cancelled_receivers {
+            log::info!("Waiting receiver cancelled: '{}'", path);
+            let res = Response::builder()
+                .status(410)
+                .header("Content-Type", "text/plain")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from(format!("[ERROR] {}\n", message)))
+                .unwrap();
+            let _ = data_receiver.res_sender.send(res);
+        }
+        let res = Response::builder()
+            .status(200)
+            .header("Content-Type", "text/plain")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(Body::from(format!("[INFO] {}\n", message)))
+            .unwrap();
+        let _ = res_sender.send(res);
+    }
+
+    // Tell whether a sender is waiting and what it sends without pairing with it
+    fn peek(&self, path: &str, as_json: bool, res_sender: oneshot::Sender<Response<Body>>) {
+        let path_to_sender = self.path_to_sender.read().unwrap();
+        let data_sender = match path_to_sender.get(path).and_then(VecDeque::front) {
+            Some(data_sender) => data_sender,
+            None => {
+                let res = Response::builder()
+                    .status(404)
+                    .header("Content-Type", "text/plain")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(format!(
+                        "[ERROR] No sender is waiting on '{}'.\n",
+                        path
+                    )))
+                    .unwrap();
+                let _ = res_sender.send(res);
+                return;
+            }
+        };
+        let headers = data_sender.req.headers();
+        // NOTE: Headers of a multipart upload are unknown until its body is read
+        let transfer_request = if is_multipart_form_data(headers) {
+            TransferRequest {
+                content_type: None,
+                content_length: None,
+                content_disposition: None,
+                body: Body::empty(),
+            }
+        } else {
+            raw_transfer_request(headers, Body::empty())
+        };
+        let x_piping: Vec<http::HeaderValue> =
+            headers.get_all("x-piping").into_iter().cloned().collect();
+        let res = if as_json {
+            let to_str = |value: &Option<http::HeaderValue>| -> Option<String> {
+                value
+                    .as_ref()
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| value.to_string())
+            };
+            let metadata = serde_json::json!({
+                "content_type": to_str(&transfer_request.content_type),
+                "content_length": to_str(&transfer_request.content_length)
+                    .and_then(|value| value.parse::<u64>().ok()),
+                "content_disposition": to_str(&transfer_request.content_disposition),
+                "x_piping": x_piping
+                    .iter()
+                    .filter_map(|value| value.to_str().ok())
+                    .collect::<Vec<_>>(),
+                "n_receivers": data_sender.n_receivers,
+            });
+            Response::builder()
+                .status(200)
+                .header("Content-Type", "application/json")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from(format!("{}\n", metadata)))
+                .unwrap()
+        } else {
+            Response::builder()
+                .status(200)
+                .option_header("Content-Type", transfer_request.content_type)
+                .option_header("Content-Length", transfer_request.content_length)
+                .option_header("Content-Disposition", transfer_request.content_disposition)
+                .header_values("X-Piping", x_piping.iter().cloned())
+                .header("Access-Control-Allow-Origin", "*")
+                .option_header(
+                    "Access-Control-Expose-Headers",
+                    if x_piping.is_empty() {
+                        None
+                    } else {
+                        Some("X-Piping")
+                    },
+                )
+                .header("X-Robots-Tag", "none")
+                .body(Body::empty())
+                .unwrap()
+        };
+        let _ = res_sender.send(res);
+    }
+
+    // Pass the response to a waiting receiver, or free its place if it disconnects or times out before that
+    async fn forward_waiting_receiver_response(
+        &self,
+        path: String,
+        mut parked_res_receiver: oneshot::Receiver<Response<Body>>,
+        mut res_sender: oneshot::Sender<Response<Body>>,
+    ) {
+        let (parked_res, timed_out) = match futures::future::select(
+            &mut parked_res_receiver,
+            futures::future::select(
+                res_sender.cancellation(),
+                Box::pin(wait_timeout(self.config.receiver_wait_timeout)),
+            ),
         )
-        .header("X-Robots-Tag", "none")
-        .body(receiver_res_body)
-        .unwrap();
-    // Return response to receiver
-    data_receiver.res_sender.send(receiver_res).unwrap();
-
-    data_sender
-        .res_body_streams_sender
-        .write()
-        .unwrap()
-        .unbounded_send(
-            one_stream(Ok(Bytes::from(
-                "[INFO] Start sending to 1 receiver(s)...\n",
+        .await
+        {
+            futures::future::Either::Left((parked_res, _)) => (parked_res, false),
+            futures::future::Either::Right((futures::future::Either::Left(_), _)) => {
+                (Err(oneshot::Canceled), false)
+            }
+            futures::future::Either::Right((futures::future::Either::Right(_), _)) => {
+                (Err(oneshot::Canceled), true)
+            }
+        };
+        if let Ok(res) = parked_res {
+            let _ = res_sender.send(res);
+            return;
+        }
+        if !timed_out {
+            drop(parked_res_receiver);
+            self.remove_disconnected_receivers(&path);
+            return;
+        }
+        let removed = {
+            let mut path_to_receivers = self.path_to_receivers.write().unwrap();
+            match path_to_receivers.get_mut(&path) {
+                Some(data_receivers) => {
+                    let removed = data_receivers.remove_receivers(|data_receiver| {
+                        data_receiver
+                            .res_sender
+                            .is_connected_to(&parked_res_receiver)
+                    });
+                    if data_receivers.is_empty() {
+                        path_to_receivers.remove(&path);
+                    }
+                    !removed.is_empty()
+                }
+                None => false,
+            }
+        };
+        // The transfer has started just before timeout
+        if !removed {
+            if let Ok(res) = parked_res_receiver.await {
+                let _ = res_sender.send(res);
+            }
+            return;
+        }
+        log::info!("Waiting receiver timed out: '{}'", path);
+        let res = Response::builder()
+            .status(408)
+            .header("Content-Type", "text/plain")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(Body::from(format!(
+                "[ERROR] Timed out waiting for a sender on '{}'.\n",
+                path
             )))
-            .chain(
-                // Wait for sender's request body finished
-                sender_req_body_finish_waiter
-                    .into_stream()
-                    .map(|_| Ok(Bytes::new())),
+            .unwrap();
+        let _ = res_sender.send(res);
+    }
+
+    fn remove_disconnected_receivers(&self, path: &str) {
+        let mut path_to_receivers = self.path_to_receivers.write().unwrap();
+        let data_receivers = match path_to_receivers.get_mut(path) {
+            Some(data_receivers) => data_receivers,
+            None => return,
+        };
+        let removed =
+            data_receivers.remove_receivers(|data_receiver| data_receiver.res_sender.is_canceled());
+        if removed.is_empty() {
+            return;
+        }
+        log::info!("Waiting receiver disconnected: '{}'", path);
+        if data_receivers.is_empty() {
+            path_to_receivers.remove(path);
+        }
+    }
+
+    // Free the places of senders waiting for "100 Continue" which have left
+    // NOTE: Only a new sender which would be rejected or queued for them probes them
+    async fn free_left_held_senders(&self, path: &str) {
+        if self.path_to_broadcaster.read().unwrap().contains_key(path) {
+            return;
+        }
+        let probes: Vec<(
+            mpsc::UnboundedSender<oneshot::Sender<bool>>,
+            oneshot::Receiver<bool>,
+        )> = {
+            let path_to_sender = self.path_to_sender.read().unwrap();
+            let data_senders = match path_to_sender.get(path) {
+                Some(data_senders) => data_senders,
+                None => return,
+            };
+            data_senders
+                .iter()
+                .filter_map(|data_sender| {
+                    let held_res = data_sender.held_res.as_ref()?;
+                    let (probe_result_sender, probe_result_receiver) = oneshot::channel::<bool>();
+                    held_res
+                        .probe_sender
+                        .unbounded_send(probe_result_sender)
+                        .ok()?;
+                    Some((held_res.probe_sender.clone(), probe_result_receiver))
+                })
+                .collect()
+        };
+        let probe_results = futures::future::join_all(probes.into_iter().map(
+            |(probe_sender, probe_result_receiver)| async move {
+                let probe_result =
+                    tokio::time::timeout(HELD_SENDER_PROBE_TIMEOUT, probe_result_receiver).await;
+                (probe_sender, probe_result)
+            },
+        ))
+        .await;
+        // NOTE: A sender slow to send its body is regarded as connected
+        let left_probe_senders: Vec<mpsc::UnboundedSender<oneshot::Sender<bool>>> = probe_results
+            .into_iter()
+            .filter(|(_, probe_result)| matches!(probe_result, Ok(Ok(false))))
+            .map(|(probe_sender, _)| probe_sender)
+            .collect();
+        if left_probe_senders.is_empty() {
+            return;
+        }
+        let mut path_to_sender = self.path_to_sender.write().unwrap();
+        let n_connected_receivers = self
+            .path_to_receivers
+            .read()
+            .unwrap()
+            .get(path)
+            .map_or(0, |data_receivers| data_receivers.receivers.len());
+        for probe_sender in left_probe_senders {
+            // NOTE: The watcher may have freed the place already
+            let index = path_to_sender.get(path).and_then(|data_senders| {
+                data_senders.iter().position(|data_sender| {
+                    matches!(&data_sender.held_res, Some(held_res) if held_res.probe_sender.same_receiver(&probe_sender))
+                })
+            });
+            if let Some(index) = index {
+                remove_waiting_sender(&mut path_to_sender, path, index, n_connected_receivers);
+                log::info!("Waiting sender disconnected: '{}'", path);
+            }
+        }
+    }
+
+    // Free the place of a waiting sender when it disconnects or times out
+    async fn watch_waiting_sender(
+        &self,
+        path: String,
+        mut unparked_waiter: oneshot::Receiver<()>,
+        sender_res_body_drop_waiter: oneshot::Receiver<()>,
+    ) {
+        // NOTE: In HTTP/1.1, disconnection is detected after the request body has been read
+        let timed_out = match futures::future::select(
+            &mut unparked_waiter,
+            futures::future::select(
+                sender_res_body_drop_waiter,
+                Box::pin(wait_timeout(self.config.sender_wait_timeout)),
+            ),
+        )
+        .await
+        {
+            // The transfer has started
+            futures::future::Either::Left(_) => return,
+            futures::future::Either::Right((futures::future::Either::Left(_), _)) => false,
+            futures::future::Either::Right((futures::future::Either::Right(_), _)) => true,
+        };
+        let mut data_sender = {
+            let mut path_to_sender = self.path_to_sender.write().unwrap();
+            // NOTE: The sender may have started transfer just now
+            let index = path_to_sender.get(&path).and_then(|data_senders| {
+                data_senders.iter().position(|data_sender| {
+                    data_sender
+                        .unparked_notifier
+                        .is_connected_to(&unparked_waiter)
+                })
+            });
+            let index = match index {
+                Some(index) => index,
+                None => return,
+            };
+            let n_connected_receivers = self
+                .path_to_receivers
+                .read()
+                .unwrap()
+                .get(&path)
+                .map_or(0, |data_receivers| data_receivers.receivers.len());
+            remove_waiting_sender(&mut path_to_sender, &path, index, n_connected_receivers)
+        };
+        if timed_out {
+            log::info!("Waiting sender timed out: '{}'", path);
+            data_sender.send_event(SenderEvent::Error {
+                message: format!("Timed out waiting for receivers on '{}'.", path),
+            });
+            data_sender.release_res();
+        } else {
+            log::info!("Waiting sender disconnected: '{}'", path);
+        }
+    }
+
+    // Pair two peers on the same path and relay each request body to the other's response
+    async fn duplex(
+        &self,
+        path: String,
+        req: Request<Body>,
+        res_sender: oneshot::Sender<Response<Body>>,
+    ) {
+        let peer = {
+            let mut path_to_duplex_peer = self.path_to_duplex_peer.write().unwrap();
+            match path_to_duplex_peer.remove(&path) {
+                // NOTE: A peer which has left is replaced
+                Some(peer) if !peer.data_receiver.res_sender.is_canceled() => peer,
+                // Wait for the other peer
+                _ => {
+                    path_to_duplex_peer.insert(
+                        path,
+                        DuplexPeer {
+                            req,
+                            data_receiver: DataReceiver {
+                                res_sender,
+                                cancel_token: None,
+                                archive_format: None,
+                            },
+                        },
+                    );
+                    return;
+                }
+            }
+        };
+        log::info!("Duplex start: '{}'", path);
+        let (peer_to_self, self_to_peer) = futures::future::join(
+            self.start_relay(
+                path.clone(),
+                peer.req,
+                vec![DataReceiver {
+                    res_sender,
+                    cancel_token: None,
+                    archive_format: None,
+                }],
+                false,
+            ),
+            self.start_relay(path.clone(), req, vec![peer.data_receiver], false),
+        )
+        .await;
+        match (peer_to_self, self_to_peer) {
+            (Ok(peer_to_self_progress), Ok(self_to_peer_progress)) => {
+                tokio::spawn(
+                    futures::future::join(
+                        peer_to_self_progress.finish_waiter,
+                        self_to_peer_progress.finish_waiter,
+                    )
+                    .map(move |_| log::info!("Duplex end: '{}'", path)),
+                );
+            }
+            (Err(err), _) | (_, Err(err)) => {
+                log::error!("Duplex error: '{}': {}", path, err);
+            }
+        }
+    }
+
+    // Spool sender's body on disk so that a receiver can get it later
+    async fn store_mail(
+        &self,
+        path: String,
+        req: Request<Body>,
+        res_sender: oneshot::Sender<Response<Body>>,
+    ) {
+        let storage = match &self.storage {
+            Some(storage) => storage,
+            None => {
+                let res = Response::builder()
+                    .status(400)
+                    .header("Content-Type", "text/plain")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(
+                        "[ERROR] Mailbox mode is disabled. (--mailbox-dir)\n",
+                    ))
+                    .unwrap();
+                let _ = res_sender.send(res);
+                return;
+            }
+        };
+        let (parts, body) = req.into_parts();
+        let transfer_request = match get_transfer_request(&parts, body, None).await {
+            Ok(transfer_request) => transfer_request,
+            Err(err) => {
+                log::error!("Mail error: '{}': {}", path, err);
+                let res = Response::builder()
+                    .status(400)
+                    .header("Content-Type", "text/plain")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(format!("[ERROR] Failed to send: {}\n", err)))
+                    .unwrap();
+                let _ = res_sender.send(res);
+                return;
+            }
+        };
+        let x_piping: Vec<http::HeaderValue> = parts
+            .headers
+            .get_all("x-piping")
+            .into_iter()
+            .cloned()
+            .collect();
+        let receiver_res_headers = Response::builder()
+            .option_header("Content-Type", transfer_request.content_type)
+            .option_header("Content-Disposition", transfer_request.content_disposition)
+            .header_values("X-Piping", x_piping.iter().cloned())
+            .header("Access-Control-Allow-Origin", "*")
+            .option_header(
+                "Access-Control-Expose-Headers",
+                if x_piping.is_empty() {
+                    None
+                } else {
+                    Some("X-Piping")
+                },
             )
-            .chain(
-                // Notify sender when sending finished
-                one_stream(Ok(Bytes::from("[INFO] Sent successfully!\n"))),
+            .header("X-Robots-Tag", "none")
+            .body(())
+            .unwrap()
+            .headers()
+            .clone();
+        let (status, message) = match storage
+            .store(&path, receiver_res_headers, transfer_request.body)
+            .await
+        {
+            Ok(size) => {
+                log::info!("Mail stored: '{}' ({} bytes)", path, size);
+                if self.deliver_mail_to_waiting_receiver(&path) {
+                    (
+                        200,
+                        format!(
+                            "[INFO] Stored on '{}' ({} bytes) and delivered to the waiting receiver.\n",
+                            path, size
+                        ),
+                    )
+                } else {
+                    (
+                        200,
+                        format!(
+                            "[INFO] Stored on '{}' ({} bytes). It expires in {} seconds.\n",
+                            path,
+                            size,
+                            storage.ttl().as_secs()
+                        ),
+                    )
+                }
+            }
+            Err(StoreError::Exists) => (
+                400,
+                format!("[ERROR] A mail has been stored on '{}'.\n", path),
+            ),
+            Err(StoreError::TooLarge { max_size }) => (
+                413,
+                format!(
+                    "[ERROR] The body exceeds {} bytes. (--mailbox-max-size)\n",
+                    max_size
+                ),
+            ),
+            Err(StoreError::Io(err)) => {
+                log::error!("Mail error: '{}': {}", path, err);
+                (500, "[ERROR] Failed to store the body.\n".to_owned())
+            }
+            Err(StoreError::Body(err)) => (400, format!("[ERROR] Failed to send: {}\n", err)),
+        };
+        let res = Response::builder()
+            .status(status)
+            .header("Content-Type", "text/plain")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(Body::from(message))
+            .unwrap();
+        let _ = res_sender.send(res);
+    }
+
+    // Hand the mail on the path to the first receiver waiting there and return whether it is handed
+    fn deliver_mail_to_waiting_receiver(&self, path: &str) -> bool {
+        let storage = match &self.storage {
+            Some(storage) => storage,
+            None => return false,
+        };
+        // NOTE: The mail is taken under the lock so that it is not taken without a receiver
+        let (data_receiver, res) = {
+            let mut path_to_receivers = self.path_to_receivers.write().unwrap();
+            let data_receivers = match path_to_receivers.get_mut(path) {
+                Some(data_receivers) => data_receivers,
+                None => return false,
+            };
+            let index = match data_receivers
+                .receivers
+                .iter()
+                .chain(data_receivers.queued.iter())
+                .position(|data_receiver| !data_receiver.res_sender.is_canceled())
+            {
+                Some(index) => index,
+                None => return false,
+            };
+            let res = match storage.take(path) {
+                Some(res) => res,
+                None => return false,
+            };
+            let mut i = 0;
+            let data_receiver = data_receivers
+                .remove_receivers(|_| {
+                    i += 1;
+                    return i - 1 == index;
+                })
+                .pop()
+                .unwrap();
+            if data_receivers.is_empty() {
+                path_to_receivers.remove(path);
+            }
+            (data_receiver, res)
+        };
+        log::info!("Mail delivered: '{}'", path);
+        let _ = data_receiver.res_sender.send(res);
+        return true;
+    }
+
+    // Relay sender's body to receivers joining and leaving at any time
+    async fn broadcast(
+        &self,
+        path: String,
+        req: Request<Body>,
+        res_sender: oneshot::Sender<Response<Body>>,
+    ) {
+        let (parts, body) = req.into_parts();
+        let res_format = SenderResFormat::negotiate(
+            get_query_params(&parts.uri).get(FORMAT_QUERY_PARAMETER_NAME),
+            parts.headers.get("accept"),
+        );
+        let transfer_request = match get_transfer_request(&parts, body, None).await {
+            Ok(transfer_request) => transfer_request,
+            Err(err) => {
+                log::error!("Broadcast error: '{}': {}", path, err);
+                let res = Response::builder()
+                    .status(400)
+                    .header("Content-Type", "text/plain")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(format!("[ERROR] Failed to send: {}\n", err)))
+                    .unwrap();
+                let _ = res_sender.send(res);
+                return;
+            }
+        };
+        let x_piping: Vec<http::HeaderValue> = parts
+            .headers
+            .get_all("x-piping")
+            .into_iter()
+            .cloned()
+            .collect();
+        // NOTE: Content-Length is not passed because receivers get the body from the middle
+        let receiver_res_headers = Response::builder()
+            .option_header("Content-Type", transfer_request.content_type)
+            .option_header("Content-Disposition", transfer_request.content_disposition)
+            .header_values("X-Piping", x_piping.iter().cloned())
+            .header("Access-Control-Allow-Origin", "*")
+            .option_header(
+                "Access-Control-Expose-Headers",
+                if x_piping.is_empty() {
+                    None
+                } else {
+                    Some("X-Piping")
+                },
             )
-            .chain(one_stream(Ok(Bytes::new())).map(move |x| {
-                log::info!("Transfer end: '{}'", path);
-                x
-            }))
-            .boxed(),
+            .header("X-Robots-Tag", "none")
+            .body(())
+            .unwrap()
+            .headers()
+            .clone();
+        let broadcaster = Arc::new(Broadcaster::new(receiver_res_headers));
+        let data_receivers: Vec<DataReceiver> = {
+            let path_to_sender = self.path_to_sender.read().unwrap();
+            let mut path_to_receivers = self.path_to_receivers.write().unwrap();
+            let mut path_to_broadcaster = self.path_to_broadcaster.write().unwrap();
+            if path_to_sender.contains_key(&path) || path_to_broadcaster.contains_key(&path) {
+                let res = Response::builder()
+                    .status(400)
+                    .header("Content-Type", "text/plain")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(format!(
+                        "[ERROR] Another sender has been connected on '{}'.\n",
+                        path
+                    )))
+                    .unwrap();
+                let _ = res_sender.send(res);
+                return;
+            }
+            path_to_broadcaster.insert(path.clone(), Arc::clone(&broadcaster));
+            // Receivers which have been waiting join from the beginning
+            match path_to_receivers.remove(&path) {
+                Some(data_receivers) => data_receivers
+                    .receivers
+                    .into_iter()
+                    .chain(data_receivers.queued)
+                    .collect(),
+                None => Vec::new(),
+            }
+        };
+        let n_receivers = data_receivers.len() as u32;
+        for data_receiver in data_receivers {
+            let _ = data_receiver.res_sender.send(broadcaster.join());
+        }
+        log::info!("Broadcast start: '{}'", path);
+
+        let (finish_notifier, finish_waiter) = oneshot::channel::<Result<(), String>>();
+        let n_bytes = Arc::new(AtomicU64::new(0));
+        let event_path = path.clone();
+        let sender_res_body = sending_events(
+            res_format,
+            SenderEvent::Start { n_receivers },
+            RelayProgress {
+                finish_waiter,
+                n_bytes: Arc::clone(&n_bytes),
+            },
         )
-        .unwrap();
-    return Ok(());
+        .map(move |event| {
+            Ok::<_, std::convert::Infallible>(res_format.render(&event_path, &event))
+        });
+        let sender_res = Response::builder()
+            .header("Content-Type", res_format.content_type())
+            .header("Access-Control-Allow-Origin", "*")
+            .body(Body::wrap_stream(sender_res_body))
+            .unwrap();
+        let _ = res_sender.send(sender_res);
+
+        let path_to_broadcaster = Arc::clone(&self.path_to_broadcaster);
+        let mut stream = transfer_request.body;
+        tokio::spawn(async move {
+            let result = loop {
+                match stream.next().await {
+                    Some(Ok(chunk)) => {
+                        n_bytes.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                        broadcaster.send(chunk);
+                    }
+                    Some(Err(err)) => break Err(err),
+                    None => break Ok(()),
+                }
+            };
+            {
+                let mut path_to_broadcaster = path_to_broadcaster.write().unwrap();
+                if matches!(path_to_broadcaster.get(&path), Some(b) if Arc::ptr_eq(b, &broadcaster))
+                {
+                    path_to_broadcaster.remove(&path);
+                }
+            }
+            match result {
+                Ok(()) => {
+                    log::info!("Broadcast end: '{}'", path);
+                    let _ = finish_notifier.send(Ok(()));
+                }
+                Err(err) => {
+                    log::info!("Broadcast failed: '{}': {}", path, err);
+                    broadcaster.abort("sender aborted");
+                    let _ = finish_notifier.send(Err(format!("Failed to send: {}", err)));
+                }
+            }
+        });
+    }
+
+    // Pass the client's request to a worker, or wait for one
+    fn rpc_call(
+        &self,
+        path: String,
+        req: Request<Body>,
+        res_sender: oneshot::Sender<Response<Body>>,
+    ) {
+        let res_sender = self.watch_rpc_peer(path.clone(), res_sender);
+        let worker_res_sender = {
+            let mut path_to_rpc_peers = self.path_to_rpc_peers.write().unwrap();
+            let rpc_peers = path_to_rpc_peers.entry(path.clone()).or_default();
+            // Skip the workers which have left
+            rpc_peers.workers.retain(|worker| !worker.is_canceled());
+            match rpc_peers.workers.pop_front() {
+                Some(worker_res_sender) => worker_res_sender,
+                None => {
+                    rpc_peers.clients.push_back(RpcClient { req, res_sender });
+                    return;
+                }
+            }
+        };
+        self.rpc_pair(&path, RpcClient { req, res_sender }, worker_res_sender);
+    }
+
+    // Wait for a client's request as a worker
+    fn rpc_wait(&self, path: &str, res_sender: oneshot::Sender<Response<Body>>) {
+        let res_sender = self.watch_rpc_peer(path.to_string(), res_sender);
+        let client = {
+            let mut path_to_rpc_peers = self.path_to_rpc_peers.write().unwrap();
+            let rpc_peers = path_to_rpc_peers.entry(path.to_string()).or_default();
+            // Skip the clients which have left
+            rpc_peers
+                .clients
+                .retain(|client| !client.res_sender.is_canceled());
+            match rpc_peers.clients.pop_front() {
+                Some(client) => client,
+                None => {
+                    rpc_peers.workers.push_back(res_sender);
+                    return;
+                }
+            }
+        };
+        self.rpc_pair(path, client, res_sender);
+    }
+
+    fn rpc_pair(
+        &self,
+        path: &str,
+        client: RpcClient,
+        worker_res_sender: oneshot::Sender<Response<Body>>,
+    ) {
+        {
+            let mut path_to_rpc_peers = self.path_to_rpc_peers.write().unwrap();
+            if matches!(path_to_rpc_peers.get(path), Some(rpc_peers) if rpc_peers.clients.is_empty() && rpc_peers.workers.is_empty())
+            {
+                path_to_rpc_peers.remove(path);
+            }
+        }
+        let rpc_id = generate_token();
+        log::info!("RPC start: '{}' ({})", path, rpc_id);
+        self.rpc_id_to_client_res_sender
+            .write()
+            .unwrap()
+            .insert(rpc_id.clone(), client.res_sender);
+        let (serialized_req, serialized_req_finish_waiter) =
+            finish_detectable_stream(rpc::serialize_request(client.req));
+        let worker_res = Response::builder()
+            .header("Content-Type", "message/http")
+            .header(rpc::RPC_ID_HEADER_NAME, rpc_id.as_str())
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Expose-Headers", rpc::RPC_ID_HEADER_NAME)
+            .body(Body::wrap_stream(serialized_req))
+            .unwrap();
+        // NOTE: The worker has left when the request is dropped before its end, even before it is sent
+        let _ = worker_res_sender.send(worker_res);
+        let piping_server = self.clone();
+        tokio::spawn(async move {
+            if let Ok(Ok(())) = serialized_req_finish_waiter.await {
+                return;
+            }
+            let client_res_sender = piping_server
+                .rpc_id_to_client_res_sender
+                .write()
+                .unwrap()
+                .remove(&rpc_id);
+            if let Some(client_res_sender) = client_res_sender {
+                log::info!("RPC worker disconnected: ({})", rpc_id);
+                let res = Response::builder()
+                    .status(502)
+                    .header("Content-Type", "text/plain")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from("[ERROR] The worker has disconnected.\n"))
+                    .unwrap();
+                let _ = client_res_sender.send(res);
+            }
+        });
+    }
+
+    // Give a sender of the response to an RPC peer, which forgets the peer when it leaves
+    fn watch_rpc_peer(
+        &self,
+        path: String,
+        mut res_sender: oneshot::Sender<Response<Body>>,
+    ) -> oneshot::Sender<Response<Body>> {
+        let (peer_res_sender, mut peer_res_receiver) = oneshot::channel::<Response<Body>>();
+        let piping_server = self.clone();
+        tokio::spawn(async move {
+            let res =
+                match futures::future::select(&mut peer_res_receiver, res_sender.cancellation())
+                    .await
+                {
+                    futures::future::Either::Left((Ok(res), _)) => res,
+                    futures::future::Either::Left((Err(_), _)) => return,
+                    // The peer has left
+                    futures::future::Either::Right(_) => {
+                        // NOTE: The peer's sender is found canceled after this
+                        drop(peer_res_receiver);
+                        piping_server.forget_left_rpc_peers(&path);
+                        return;
+                    }
+                };
+            let _ = res_sender.send(res);
+        });
+        return peer_res_sender;
+    }
+
+    // Forget the RPC clients and workers which have left
+    fn forget_left_rpc_peers(&self, path: &str) {
+        {
+            let mut path_to_rpc_peers = self.path_to_rpc_peers.write().unwrap();
+            if let Some(rpc_peers) = path_to_rpc_peers.get_mut(path) {
+                rpc_peers
+                    .clients
+                    .retain(|client| !client.res_sender.is_canceled());
+                rpc_peers.workers.retain(|worker| !worker.is_canceled());
+                if rpc_peers.clients.is_empty() && rpc_peers.workers.is_empty() {
+                    path_to_rpc_peers.remove(path);
+                }
+            }
+        }
+        self.rpc_id_to_client_res_sender
+            .write()
+            .unwrap()
+            .retain(|_, client_res_sender| !client_res_sender.is_canceled());
+    }
+
+    // Relay the worker's reply to the client
+    async fn rpc_reply(&self, req: Request<Body>, res_sender: oneshot::Sender<Response<Body>>) {
+        let rpc_id = req
+            .headers()
+            .get(rpc::RPC_ID_HEADER_NAME)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+        let client_res_sender = self
+            .rpc_id_to_client_res_sender
+            .write()
+            .unwrap()
+            .remove(&rpc_id);
+        let client_res_sender = match client_res_sender {
+            Some(client_res_sender) => client_res_sender,
+            None => {
+                let res = Response::builder()
+                    .status(404)
+                    .header("Content-Type", "text/plain")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(format!(
+                        "[ERROR] No client is waiting for the reply '{}'.\n",
+                        rpc_id
+                    )))
+                    .unwrap();
+                let _ = res_sender.send(res);
+                return;
+            }
+        };
+        let (status, message) = match rpc::parse_reply(req.into_body()).await {
+            Ok(client_res) => match client_res_sender.send(client_res) {
+                Ok(()) => {
+                    log::info!("RPC end: ({})", rpc_id);
+                    (200, "[INFO] Replied to the client.\n".to_owned())
+                }
+                Err(_) => (410, "[ERROR] The client has disconnected.\n".to_owned()),
+            },
+            Err(message) => {
+                let res = Response::builder()
+                    .status(502)
+                    .header("Content-Type", "text/plain")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(format!("[ERROR] {}\n", message)))
+                    .unwrap();
+                let _ = client_res_sender.send(res);
+                (400, format!("[ERROR] {}\n", message))
+            }
+        };
+        let res = Response::builder()
+            .status(status)
+            .header("Content-Type", "text/plain")
+            .header("Access-Control-Allow-Origin", "*")
+            .body(Body::from(message))
+            .unwrap();
+        let _ = res_sender.send(res);
+    }
+
+    // Splice the upgraded connections of two peers CONNECTing to the same path
+    fn tunnel(
+        &self,
+        path: String,
+        mut req: Request<Body>,
+        res_sender: oneshot::Sender<Response<Body>>,
+    ) {
+        let on_upgrade = hyper::upgrade::on(&mut req);
+        let peer = {
+            let mut path_to_tunnel_peer = self.path_to_tunnel_peer.write().unwrap();
+            match path_to_tunnel_peer.remove(&path) {
+                // NOTE: A peer which has left is replaced
+                Some(peer) if !peer.res_sender.is_canceled() => peer,
+                _ => {
+                    path_to_tunnel_peer.insert(
+                        path,
+                        TunnelPeer {
+                            on_upgrade,
+                            res_sender,
+                        },
+                    );
+                    return;
+                }
+            }
+        };
+        log::info!("Tunnel start: '{}'", path);
+        let TunnelPeer {
+            on_upgrade: peer_on_upgrade,
+            res_sender: peer_res_sender,
+        } = peer;
+        for res_sender in [peer_res_sender, res_sender] {
+            let res = Response::builder().status(200).body(Body::empty()).unwrap();
+            let _ = res_sender.send(res);
+        }
+        tokio::spawn(async move {
+            match futures::future::try_join(peer_on_upgrade, on_upgrade).await {
+                Ok((mut upgraded1, mut upgraded2)) => {
+                    match tokio::io::copy_bidirectional(&mut upgraded1, &mut upgraded2).await {
+                        Ok((n_bytes1, n_bytes2)) => log::info!(
+                            "Tunnel end: '{}' ({} bytes, {} bytes)",
+                            path,
+                            n_bytes1,
+                            n_bytes2
+                        ),
+                        Err(err) => log::info!("Tunnel closed: '{}': {}", path, err),
+                    }
+                }
+                Err(err) => log::error!("Tunnel error: '{}': {}", path, err),
+            }
+        });
+    }
+
+    // Respond to receivers and relay sender's request body to them
+    async fn start_relay(
+        &self,
+        path: String,
+        req: Request<Body>,
+        data_receivers: Vec<DataReceiver>,
+        resumable: bool,
+    ) -> Result<RelayProgress, std::io::Error> {
+        let (data_sender_parts, data_sender_body) = req.into_parts();
+        // Archive asked by all receivers in common
+        let receivers_archive_format = data_receivers
+            .iter()
+            .map(|data_receiver| data_receiver.archive_format)
+            .reduce(|a, b| if a == b { a } else { None })
+            .flatten();
+        // Extract transfer headers and body even when request is multipart
+        let transfer_request = match get_transfer_request(
+            &data_sender_parts,
+            data_sender_body,
+            receivers_archive_format,
+        )
+        .await
+        {
+            Ok(transfer_request) => transfer_request,
+            Err(err) => {
+                // NOTE: Receivers are rejected before their responses start
+                for data_receiver in data_receivers {
+                    let res = Response::builder()
+                        .status(400)
+                        .header("Content-Type", "text/plain")
+                        .header("Access-Control-Allow-Origin", "*")
+                        .body(Body::from(format!("[ERROR] Failed to send: {}\n", err)))
+                        .unwrap();
+                    let _ = data_receiver.res_sender.send(res);
+                }
+                return Err(err);
+            }
+        };
+        // NOTE: The relay tells the end of the transfer instead of the body
+        let (finish_detectable_body, _) = finish_detectable_stream(transfer_request.body);
+        let n_bytes = finish_detectable_body.n_bytes();
+        let x_piping: Vec<http::HeaderValue> = data_sender_parts
+            .headers
+            .get_all("x-piping")
+            .into_iter()
+            .cloned()
+            .collect();
+        let has_x_piping = data_sender_parts.headers.contains_key("x-piping");
+        let complete_length: Option<u64> = transfer_request
+            .content_length
+            .as_ref()
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+        let declared_end: Option<u64> = match data_sender_parts
+            .headers
+            .get("content-range")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_content_range)
+        {
+            Some(content_range) => Some(content_range.end + 1),
+            None => complete_length,
+        };
+        let mut receiver_body_senders = Vec::with_capacity(data_receivers.len());
+        let mut receiver_res_headers = http::HeaderMap::new();
+        for data_receiver in data_receivers {
+            // Create receiver's body
+            let (receiver_body_sender, receiver_res_body) = receiver_body_channel(0, Vec::new());
+            // Create receiver's response
+            let receiver_res = Response::builder()
+                .option_header("Content-Type", transfer_request.content_type.clone())
+                .option_header("Content-Length", transfer_request.content_length.clone())
+                .option_header(
+                    "Content-Disposition",
+                    transfer_request.content_disposition.clone(),
+                )
+                .header_values("X-Piping", x_piping.iter().cloned())
+                .header("Access-Control-Allow-Origin", "*")
+                .option_header(
+                    "Access-Control-Expose-Headers",
+                    if has_x_piping { Some("X-Piping") } else { None },
+                )
+                .header("X-Robots-Tag", "none")
+                .body(receiver_res_body)
+                .unwrap();
+            receiver_res_headers = receiver_res.headers().clone();
+            // Return response to receiver
+            if data_receiver.res_sender.send(receiver_res).is_ok() {
+                receiver_body_senders.push(receiver_body_sender);
+            }
+        }
+        let replay_window = if resumable && self.config.replay_window_size > 0 {
+            Some(ReplayWindow::register(
+                path.clone(),
+                self.config.replay_window_size,
+                self.config.receiver_resume_grace_period,
+                receiver_res_headers,
+                Arc::clone(&self.path_to_rejoin_request_sender),
+            ))
+        } else {
+            None
+        };
+        // NOTE: Only a sender sending by Content-Range can resume, so the others' receivers are aborted at once
+        let sender_resumable = resumable && data_sender_parts.headers.contains_key("content-range");
+        // Relay sender's body to all receivers' bodies
+        let (finish_notifier, finish_waiter) = oneshot::channel::<Result<(), String>>();
+        tokio::spawn(relay(
+            path,
+            self.read_ahead(finish_detectable_body),
+            Relay {
+                receiver_body_senders,
+                n_transferred_bytes: 0,
+                complete_length,
+                declared_end,
+                replay_window,
+                n_gone_receiver_bytes: 0,
+            },
+            finish_notifier,
+            if sender_resumable {
+                Some(Arc::clone(&self.path_to_interrupted_transfer))
+            } else {
+                None
+            },
+            self.config.sender_resume_timeout,
+        ));
+        return Ok(RelayProgress {
+            finish_waiter,
+            n_bytes,
+        });
+    }
+
+    // Read sender's body ahead of receivers when --read-ahead-buffer-size is given
+    fn read_ahead<S>(
+        &self,
+        stream: S,
+    ) -> impl Stream<Item = Result<Bytes, hyper::Error>> + Unpin + Send
+    where
+        S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin + Send + 'static,
+    {
+        if self.config.read_ahead_buffer_size == 0 {
+            return futures::future::Either::Left(stream);
+        }
+        return futures::future::Either::Right(read_ahead(
+            stream,
+            self.config.read_ahead_buffer_size,
+            Arc::clone(&self.read_ahead_memory),
+        ));
+    }
+
+    // Continue an interrupted transfer with the sender's request having Content-Range
+    fn resume_transfer(
+        &self,
+        path: String,
+        req: Request<Body>,
+        content_range: ContentRange,
+        res_sender: oneshot::Sender<Response<Body>>,
+    ) {
+        let interrupted_transfer = {
+            let mut path_to_interrupted_transfer =
+                self.path_to_interrupted_transfer.write().unwrap();
+            let error_message: Option<String> = match path_to_interrupted_transfer.get(&path) {
+                None => Some(format!(
+                    "[ERROR] No interrupted transfer to resume on '{}'.\n",
+                    path
+                )),
+                Some(interrupted_transfer) => {
+                    let relay = &interrupted_transfer.relay;
+                    if relay.n_transferred_bytes != content_range.start {
+                        Some(format!(
+                            "[ERROR] Content-Range should start at {}, but {}.\n",
+                            relay.n_transferred_bytes, content_range.start
+                        ))
+                    } else if content_range.complete_length.is_some()
+                        && relay.complete_length.is_some()
+                        && content_range.complete_length != relay.complete_length
+                    {
+                        Some(format!(
+                            "[ERROR] Content-Range complete length should be {}.\n",
+                            relay.complete_length.unwrap()
+                        ))
+                    } else {
+                        None
+                    }
+                }
+            };
+            if let Some(error_message) = error_message {
+                let res = Response::builder()
+                    .status(400)
+                    .header("Content-Type", "text/plain")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(error_message))
+                    .unwrap();
+                res_sender.send(res).unwrap();
+                return;
+            }
+            path_to_interrupted_transfer.remove(&path).unwrap()
+        };
+        log::info!(
+            "Transfer resume: '{}' from {} bytes",
+            path,
+            content_range.start
+        );
+        let res_format = SenderResFormat::negotiate(
+            get_query_params(req.uri()).get(FORMAT_QUERY_PARAMETER_NAME),
+            req.headers().get("accept"),
+        );
+        let (finish_detectable_body, _) = finish_detectable_stream(req.into_body());
+        let (finish_notifier, finish_waiter) = oneshot::channel::<Result<(), String>>();
+        let relay_progress = RelayProgress {
+            n_bytes: finish_detectable_body.n_bytes(),
+            finish_waiter,
+        };
+        let sender_res_body = Body::wrap_stream(
+            sending_events(
+                res_format,
+                SenderEvent::Resume {
+                    start: content_range.start,
+                },
+                relay_progress,
+            )
+            .map({
+                let path = path.clone();
+                move |event| Ok::<_, std::convert::Infallible>(res_format.render(&path, &event))
+            }),
+        );
+        let sender_res = Response::builder()
+            .header("Content-Type", res_format.content_type())
+            .header("Access-Control-Allow-Origin", "*")
+            .body(sender_res_body)
+            .unwrap();
+        res_sender.send(sender_res).unwrap();
+        let mut relay_state = interrupted_transfer.relay;
+        relay_state.complete_length = relay_state
+            .complete_length
+            .or(content_range.complete_length);
+        relay_state.declared_end = Some(content_range.end + 1);
+        tokio::spawn(relay(
+            path,
+            self.read_ahead(finish_detectable_body),
+            relay_state,
+            finish_notifier,
+            Some(Arc::clone(&self.path_to_interrupted_transfer)),
+            self.config.sender_resume_timeout,
+        ));
+    }
+}
+
+// Tell when the transfer is finished and how many bytes have been read from sender
+struct RelayProgress {
+    finish_waiter: oneshot::Receiver<Result<(), String>>,
+    n_bytes: Arc<AtomicU64>,
+}
+
+// Send events of the transfer to the sender's response
+fn send_sending_events(
+    res_body_streams_sender: &ResBodyStreamsSender,
+    res_format: SenderResFormat,
+    n_receivers: u32,
+    path: String,
+    relay_progress: RelayProgress,
+) {
+    let event_path = path.clone();
+    let _ = res_body_streams_sender.write().unwrap().unbounded_send(
+        sending_events(
+            res_format,
+            SenderEvent::Start { n_receivers },
+            relay_progress,
+        )
+        .inspect(move |event| match event {
+            SenderEvent::Finished { .. } => log::info!("Transfer end: '{}'", path),
+            SenderEvent::Error { message } => {
+                log::info!("Transfer failed: '{}': {}", path, message)
+            }
+            _ => {}
+        })
+        .map(move |event| Ok(res_format.render(&event_path, &event)))
+        .boxed(),
+    );
+}
+
+// Events to sender from the start of sending to the end
+fn sending_events(
+    res_format: SenderResFormat,
+    start_event: SenderEvent,
+    relay_progress: RelayProgress,
+) -> impl Stream<Item = SenderEvent> + Send {
+    let finish_waiter = relay_progress.finish_waiter.shared();
+    let n_bytes = relay_progress.n_bytes;
+    let progress_events = if res_format.reports_progress() {
+        let n_bytes = Arc::clone(&n_bytes);
+        futures::stream::unfold(
+            tokio::time::interval_at(
+                tokio::time::Instant::now() + PROGRESS_INTERVAL,
+                PROGRESS_INTERVAL,
+            ),
+            |mut interval| async move {
+                interval.tick().await;
+                Some(((), interval))
+            },
+        )
+        .map(move |_| SenderEvent::Progress {
+            n_bytes: n_bytes.load(Ordering::Relaxed),
+        })
+        .take_until(finish_waiter.clone())
+        .boxed()
+    } else {
+        futures::stream::empty().boxed()
+    };
+    one_stream(start_event).chain(progress_events).chain(
+        // Wait for the transfer finished
+        finish_waiter.into_stream().map(move |result| match result {
+            Ok(Ok(())) => SenderEvent::Finished {
+                n_bytes: n_bytes.load(Ordering::Relaxed),
+            },
+            Ok(Err(message)) => SenderEvent::Error { message },
+            Err(_) => SenderEvent::Error {
+                message: "Sending was interrupted.".to_owned(),
+            },
+        }),
+    )
+}
+
+// Relay sender's body to receivers and keep receivers waiting for resumption when it is interrupted
+async fn relay<S>(
+    path: String,
+    stream: S,
+    mut relay_state: Relay,
+    finish_notifier: oneshot::Sender<Result<(), String>>,
+    path_to_interrupted_transfer: Option<Arc<RwLock<HashMap<String, InterruptedTransfer>>>>,
+    resume_timeout: std::time::Duration,
+) where
+    S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin,
+{
+    let start = relay_state.n_transferred_bytes;
+    // NOTE: The stream is dropped at the end so that sender's request body is not read any more
+    let tee_end = tee_stream(stream, &mut relay_state).await;
+    let interrupted = match tee_end {
+        TeeEnd::SenderError(err) => {
+            log::info!("Transfer interrupted: '{}': {}", path, err);
+            let _ = finish_notifier.send(Err(format!("Failed to send: {}", err)));
+            true
+        }
+        TeeEnd::ReceiversGone => {
+            log::info!(
+                "Receiver disconnected: '{}' after {} bytes",
+                path,
+                relay_state.n_gone_receiver_bytes
+            );
+            let _ = finish_notifier.send(Err(receiver_disconnected_message(&relay_state)));
+            return;
+        }
+        TeeEnd::Oversized { n_bytes } => {
+            abort_by_length_mismatch(&path, &mut relay_state, start, n_bytes, finish_notifier)
+                .await;
+            return;
+        }
+        // Sender's body is shorter than declared
+        TeeEnd::Finished if matches!(relay_state.declared_end, Some(end) if relay_state.n_transferred_bytes < end) =>
+        {
+            let n_bytes = relay_state.n_transferred_bytes;
+            abort_by_length_mismatch(&path, &mut relay_state, start, n_bytes, finish_notifier)
+                .await;
+            return;
+        }
+        // Sender has sent a part of the content by Content-Range
+        TeeEnd::Finished if matches!(relay_state.complete_length, Some(len) if relay_state.n_transferred_bytes < len) =>
+        {
+            let _ = finish_notifier.send(Ok(()));
+            true
+        }
+        TeeEnd::Finished => {
+            let result = if relay_state.finish().await {
+                Ok(())
+            } else {
+                log::info!(
+                    "Receiver disconnected: '{}' after {} bytes",
+                    path,
+                    relay_state.n_gone_receiver_bytes
+                );
+                Err(receiver_disconnected_message(&relay_state))
+            };
+            let _ = finish_notifier.send(result);
+            return;
+        }
+    };
+    let path_to_interrupted_transfer = match path_to_interrupted_transfer {
+        Some(path_to_interrupted_transfer) if interrupted && relay_state.has_receivers() => {
+            path_to_interrupted_transfer
+        }
+        // Abort receivers not to make the truncated content look complete
+        _ => {
+            if interrupted {
+                relay_state
+                    .abort(std::io::ErrorKind::ConnectionAborted, "sender aborted")
+                    .await;
+            }
+            return;
+        }
+    };
+    let (resumed_notifier, resumed_waiter) = oneshot::channel::<()>();
+    path_to_interrupted_transfer.write().unwrap().insert(
+        path.clone(),
+        InterruptedTransfer {
+            relay: relay_state,
+            _resumed_notifier: resumed_notifier,
+        },
+    );
+    // Abort receivers when the sender does not resume in time
+    if let futures::future::Either::Left(_) =
+        futures::future::select(Box::pin(tokio::time::sleep(resume_timeout)), resumed_waiter).await
+    {
+        let interrupted_transfer = path_to_interrupted_transfer.write().unwrap().remove(&path);
+        if let Some(mut interrupted_transfer) = interrupted_transfer {
+            log::info!("Transfer resume timeout: '{}'", path);
+            interrupted_transfer
+                .relay
+                .abort(std::io::ErrorKind::TimedOut, "sender did not resume")
+                .await;
+        }
+    }
+}
+
+// Tell sender and receivers that the sender's body is different from the declared length
+async fn abort_by_length_mismatch(
+    path: &str,
+    relay_state: &mut Relay,
+    start: u64,
+    n_bytes: u64,
+    finish_notifier: oneshot::Sender<Result<(), String>>,
+) {
+    let message = format!(
+        "{} bytes were received, but {} bytes were declared.",
+        n_bytes - start,
+        relay_state.declared_end.unwrap_or(n_bytes) - start
+    );
+    log::info!("Length mismatch: '{}': {}", path, message);
+    relay_state
+        .abort(std::io::ErrorKind::InvalidData, &message)
+        .await;
+    let _ = finish_notifier.send(Err(message));
+}
+
+fn receiver_disconnected_message(relay_state: &Relay) -> String {
+    return format!(
+        "receiver disconnected after {} bytes",
+        relay_state.n_gone_receiver_bytes
+    );
+}
+
+fn get_query_params(uri: &http::Uri) -> HashMap<String, String> {
+    match uri.query() {
+        Some(query) => serde_urlencoded::from_str::<HashMap<String, String>>(query)
+            .unwrap_or_else(|_| HashMap::new()),
+        None => HashMap::new(),
+    }
+}
+
+// Take the receivers to be paired, leaving queued receivers for the next sender
+fn take_waiting_receivers(
+    path_to_receivers: &mut HashMap<String, DataReceivers>,
+    path: &str,
+) -> Vec<DataReceiver> {
+    let data_receivers = path_to_receivers.get_mut(path).unwrap();
+    let receivers = data_receivers.take_receivers();
+    if data_receivers.is_empty() {
+        path_to_receivers.remove(path);
+    }
+    return receivers;
+}
+
+// Remove a waiting sender and tell the senders behind it their new places
+fn remove_waiting_sender(
+    path_to_sender: &mut HashMap<String, VecDeque<DataSender>>,
+    path: &str,
+    index: usize,
+    n_connected_receivers: usize,
+) -> DataSender {
+    let data_senders = path_to_sender.get_mut(path).unwrap();
+    let data_sender = data_senders.remove(index).unwrap();
+    for (n_senders_ahead, data_sender) in data_senders.iter().enumerate().skip(index) {
+        if n_senders_ahead == 0 {
+            data_sender.send_waiting_events(n_connected_receivers);
+        } else {
+            data_sender.send_event(SenderEvent::Queued {
+                n_senders_ahead: n_senders_ahead as u32,
+            });
+        }
+    }
+    if data_senders.is_empty() {
+        path_to_sender.remove(path);
+    }
+    return data_sender;
+}
+
+fn get_cancel_token(headers: &http::HeaderMap) -> Option<String> {
+    return headers
+        .get("x-piping-cancel-token")
+        .and_then(|value| value.to_str().ok())
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_string());
+}
+
+fn is_multipart_form_data(headers: &http::HeaderMap) -> bool {
+    let mime_type: Option<mime::Mime> = headers
+        .get("content-type")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok());
+    return matches!(mime_type, Some(mime_type) if mime_type.essence_str() == "multipart/form-data");
+}
+
+fn expects_continue(req: &Request<Body>) -> bool {
+    return matches!(
+        req.headers().get("expect"),
+        Some(value) if value.as_bytes().eq_ignore_ascii_case(b"100-continue")
+    );
+}
+
+// Send the held response when released and pass the body on, or drop them when the client leaves
+// NOTE: In HTTP/1.1, disconnection is noticed only by probing the body, which sends "100 Continue"
+async fn hold_res(
+    mut res_sender: oneshot::Sender<Response<Body>>,
+    res: Response<Body>,
+    mut body: Body,
+    mut held_body_sender: hyper::body::Sender,
+    mut release_waiter: oneshot::Receiver<()>,
+    mut probe_receiver: mpsc::UnboundedReceiver<oneshot::Sender<bool>>,
+) {
+    let mut peeked_chunk: Option<Bytes> = None;
+    loop {
+        let probe_result_sender = match futures::future::select(
+            &mut release_waiter,
+            futures::future::select(probe_receiver.next(), res_sender.cancellation()),
+        )
+        .await
+        {
+            futures::future::Either::Left((Ok(()), _)) => break,
+            futures::future::Either::Right((
+                futures::future::Either::Left((Some(probe_result_sender), _)),
+                _,
+            )) => probe_result_sender,
+            // The sender has been dropped or the client has left
+            _ => return,
+        };
+        if peeked_chunk.is_none() {
+            match futures::future::select(body.next(), &mut release_waiter).await {
+                futures::future::Either::Left((Some(Ok(chunk)), _)) => peeked_chunk = Some(chunk),
+                futures::future::Either::Left((Some(Err(_)), _)) => {
+                    let _ = probe_result_sender.send(false);
+                    return;
+                }
+                // NOTE: An empty body is not probed
+                futures::future::Either::Left((None, _)) => {}
+                futures::future::Either::Right((Ok(()), _)) => break,
+                futures::future::Either::Right((Err(_), _)) => return,
+            }
+        }
+        let _ = probe_result_sender.send(true);
+    }
+    if res_sender.send(res).is_err() {
+        return;
+    }
+    if let Some(chunk) = peeked_chunk {
+        if held_body_sender.send_data(chunk).await.is_err() {
+            return;
+        }
+    }
+    while let Some(chunk) = body.next().await {
+        match chunk {
+            Ok(chunk) => {
+                if held_body_sender.send_data(chunk).await.is_err() {
+                    return;
+                }
+            }
+            Err(_) => {
+                held_body_sender.abort();
+                return;
+            }
+        }
+    }
+}
+
+// Resolve after the timeout, or never when no timeout is set
+async fn wait_timeout(timeout: Option<std::time::Duration>) {
+    match timeout {
+        Some(timeout) => tokio::time::sleep(timeout).await,
+        None => futures::future::pending().await,
+    }
+}
+
+// Get the number of receivers from the query parameter "n" (default: 1)
+fn get_n_receivers(uri: &http::Uri) -> Result<u32, String> {
+    match get_query_params(uri).get(N_RECEIVERS_QUERY_PARAMETER_NAME) {
+        Some(n_str) => match n_str.parse::<u32>() {
+            Ok(0) => Err("[ERROR] n should > 0, but n = 0.\n".to_owned()),
+            Ok(n) => Ok(n),
+            Err(_) => Err(format!(
+                "[ERROR] Invalid \"n\" query parameter: {}\n",
+                n_str
+            )),
+        },
+        None => Ok(1),
+    }
 }
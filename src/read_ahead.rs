@@ -0,0 +1,160 @@
+use futures::future::Either;
+use futures::stream::{Stream, StreamExt};
+use hyper::body::Bytes;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::{mpsc, Notify};
+
+// Memory for read-ahead buffers shared by all transfers
+pub struct ReadAheadMemory {
+    max_bytes: u64,
+    n_bytes: AtomicU64,
+}
+
+impl ReadAheadMemory {
+    pub fn new(max_bytes: u64) -> Self {
+        ReadAheadMemory {
+            max_bytes,
+            n_bytes: AtomicU64::new(0),
+        }
+    }
+
+    // Bytes read ahead by all transfers
+    pub fn n_bytes(&self) -> u64 {
+        self.n_bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    fn try_reserve(&self, n_bytes: u64) -> bool {
+        let mut current = self.n_bytes.load(Ordering::Relaxed);
+        loop {
+            if current + n_bytes > self.max_bytes {
+                return false;
+            }
+            match self.n_bytes.compare_exchange_weak(
+                current,
+                current + n_bytes,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return true,
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    fn release(&self, n_bytes: u64) {
+        self.n_bytes.fetch_sub(n_bytes, Ordering::Relaxed);
+    }
+}
+
+// Bytes of a transfer read ahead but not yet taken
+struct Buffer {
+    n_bytes: AtomicU64,
+    taken: Notify,
+}
+
+// Item with the bytes reserved in the memory for it
+type ReadAheadItem = (Result<Bytes, hyper::Error>, u64);
+
+// Stream read ahead from the source by a task
+pub struct ReadAheadStream {
+    item_receiver: mpsc::UnboundedReceiver<ReadAheadItem>,
+    buffer: Arc<Buffer>,
+    memory: Arc<ReadAheadMemory>,
+}
+
+impl Stream for ReadAheadStream {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let (item, n_reserved_bytes) = match futures::ready!(self.item_receiver.poll_recv(cx)) {
+            Some(item) => item,
+            None => return Poll::Ready(None),
+        };
+        self.memory.release(n_reserved_bytes);
+        if let Ok(chunk) = &item {
+            self.buffer
+                .n_bytes
+                .fetch_sub(chunk.len() as u64, Ordering::Relaxed);
+        }
+        self.buffer.taken.notify_one();
+        return Poll::Ready(Some(item));
+    }
+}
+
+impl Drop for ReadAheadStream {
+    fn drop(&mut self) {
+        // NOTE: The task releases the item which it fails to send after closing
+        self.item_receiver.close();
+        while let Ok((_, n_reserved_bytes)) = self.item_receiver.try_recv() {
+            self.memory.release(n_reserved_bytes);
+        }
+    }
+}
+
+// Read the stream ahead up to max bytes so that the source need not wait for the reader
+pub fn read_ahead<S>(mut stream: S, max_bytes: u64, memory: Arc<ReadAheadMemory>) -> ReadAheadStream
+where
+    S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin + Send + 'static,
+{
+    let (item_sender, item_receiver) = mpsc::unbounded_channel::<ReadAheadItem>();
+    let buffer = Arc::new(Buffer {
+        n_bytes: AtomicU64::new(0),
+        taken: Notify::new(),
+    });
+    {
+        let buffer = Arc::clone(&buffer);
+        let memory = Arc::clone(&memory);
+        tokio::spawn(async move {
+            loop {
+                // NOTE: The source is dropped as soon as the reader leaves not to be read any more
+                let item =
+                    match futures::future::select(stream.next(), Box::pin(item_sender.closed()))
+                        .await
+                    {
+                        Either::Left((Some(item), _)) => item,
+                        _ => return,
+                    };
+                let n_bytes = match &item {
+                    Ok(chunk) => chunk.len() as u64,
+                    Err(_) => 0,
+                };
+                // Wait for room in the buffer and the memory
+                let n_reserved_bytes = loop {
+                    let n_buffered_bytes = buffer.n_bytes.load(Ordering::Relaxed);
+                    // NOTE: An empty buffer always takes a chunk like the relay without read-ahead
+                    if n_buffered_bytes == 0 {
+                        break 0;
+                    }
+                    if n_buffered_bytes + n_bytes <= max_bytes && memory.try_reserve(n_bytes) {
+                        break n_bytes;
+                    }
+                    if let Either::Right(_) = futures::future::select(
+                        Box::pin(buffer.taken.notified()),
+                        Box::pin(item_sender.closed()),
+                    )
+                    .await
+                    {
+                        return;
+                    }
+                };
+                buffer.n_bytes.fetch_add(n_bytes, Ordering::Relaxed);
+                if item_sender.send((item, n_reserved_bytes)).is_err() {
+                    memory.release(n_reserved_bytes);
+                    return;
+                }
+            }
+        });
+    }
+    return ReadAheadStream {
+        item_receiver,
+        buffer,
+        memory,
+    };
+}
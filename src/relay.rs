@@ -0,0 +1,449 @@
+use futures::channel::{mpsc, oneshot};
+use futures::future::Either;
+use futures::sink::SinkExt;
+use futures::stream::{Stream, StreamExt};
+use http::Response;
+use hyper::body::Bytes;
+use hyper::Body;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use crate::util::finish_detectable_stream;
+
+// Sender to a receiver's body, which also tells whether the receiver got the whole body
+pub struct ReceiverBodySender {
+    sender: mpsc::Sender<Result<Bytes, std::io::Error>>,
+    finish_waiter: oneshot::Receiver<Result<(), String>>,
+    // Offset of the content where the receiver's body starts
+    start: u64,
+    // Bytes passed to the receiver's response
+    n_bytes: Arc<AtomicU64>,
+}
+
+impl ReceiverBodySender {
+    fn is_gone(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    // Offset of the content which the receiver has received until
+    fn n_received_bytes(&self) -> u64 {
+        self.start + self.n_bytes.load(Ordering::Relaxed)
+    }
+}
+
+// Create a receiver's body starting at the offset with the given chunks
+pub fn receiver_body_channel(
+    start: u64,
+    first_chunks: Vec<Result<Bytes, std::io::Error>>,
+) -> (ReceiverBodySender, Body) {
+    let (sender, receiver) = mpsc::channel::<Result<Bytes, std::io::Error>>(1);
+    let (receiver, finish_waiter) =
+        finish_detectable_stream(futures::stream::iter(first_chunks).chain(receiver));
+    let n_bytes = receiver.n_bytes();
+    (
+        ReceiverBodySender {
+            sender,
+            finish_waiter,
+            start,
+            n_bytes,
+        },
+        Body::wrap_stream(receiver),
+    )
+}
+
+// How copying sender's stream to receivers ended
+pub enum TeeEnd {
+    Finished,
+    SenderError(hyper::Error),
+    ReceiversGone,
+    // Sender has sent more than declared
+    Oversized { n_bytes: u64 },
+}
+
+pub type PathToRejoinRequestSender =
+    Arc<RwLock<HashMap<String, mpsc::UnboundedSender<RejoinRequest>>>>;
+
+// Request of a receiver coming back with "Range: bytes=<start>-"
+pub struct RejoinRequest {
+    pub start: u64,
+    pub res_sender: oneshot::Sender<Response<Body>>,
+}
+
+// Receivers' bodies and progress of a transfer, which live across sender's resumption
+pub struct Relay {
+    pub receiver_body_senders: Vec<ReceiverBodySender>,
+    pub n_transferred_bytes: u64,
+    pub complete_length: Option<u64>,
+    // Offset where the current sender declared to end by Content-Length or Content-Range
+    pub declared_end: Option<u64>,
+    pub replay_window: Option<ReplayWindow>,
+    // Max bytes which a receiver gone away had received
+    pub n_gone_receiver_bytes: u64,
+}
+
+// Recent bytes kept for receivers reconnecting with Range
+pub struct ReplayWindow {
+    path: String,
+    chunks: VecDeque<Bytes>,
+    n_bytes: usize,
+    max_bytes: usize,
+    grace_period: std::time::Duration,
+    // Deadlines of disconnected receivers until which they can come back
+    vacancy_deadlines: Vec<tokio::time::Instant>,
+    receiver_res_headers: http::HeaderMap,
+    rejoin_request_sender: mpsc::UnboundedSender<RejoinRequest>,
+    rejoin_request_receiver: mpsc::UnboundedReceiver<RejoinRequest>,
+    path_to_rejoin_request_sender: PathToRejoinRequestSender,
+}
+
+impl ReplayWindow {
+    pub fn register(
+        path: String,
+        max_bytes: usize,
+        grace_period: std::time::Duration,
+        receiver_res_headers: http::HeaderMap,
+        path_to_rejoin_request_sender: PathToRejoinRequestSender,
+    ) -> Self {
+        let (rejoin_request_sender, rejoin_request_receiver) = mpsc::unbounded();
+        path_to_rejoin_request_sender
+            .write()
+            .unwrap()
+            .insert(path.clone(), rejoin_request_sender.clone());
+        ReplayWindow {
+            path,
+            chunks: VecDeque::new(),
+            n_bytes: 0,
+            max_bytes,
+            grace_period,
+            vacancy_deadlines: Vec::new(),
+            receiver_res_headers,
+            rejoin_request_sender,
+            rejoin_request_receiver,
+            path_to_rejoin_request_sender,
+        }
+    }
+
+    fn push(&mut self, bytes: Bytes) {
+        self.n_bytes += bytes.len();
+        self.chunks.push_back(bytes);
+        while self.n_bytes > self.max_bytes {
+            match self.chunks.pop_front() {
+                Some(chunk) => self.n_bytes -= chunk.len(),
+                None => break,
+            }
+        }
+    }
+
+    fn expire_vacancies(&mut self) {
+        let now = tokio::time::Instant::now();
+        self.vacancy_deadlines.retain(|deadline| *deadline > now);
+    }
+}
+
+impl Drop for ReplayWindow {
+    fn drop(&mut self) {
+        let mut path_to_rejoin_request_sender = self.path_to_rejoin_request_sender.write().unwrap();
+        // NOTE: A newer transfer on the same path may have been registered
+        let registered_by_self = matches!(
+            path_to_rejoin_request_sender.get(&self.path),
+            Some(sender) if sender.same_receiver(&self.rejoin_request_sender)
+        );
+        if registered_by_self {
+            path_to_rejoin_request_sender.remove(&self.path);
+        }
+    }
+}
+
+impl Relay {
+    // Whether someone still receives or may come back to receive
+    pub fn has_receivers(&mut self) -> bool {
+        if let Some(replay_window) = self.replay_window.as_mut() {
+            replay_window.expire_vacancies();
+            if !replay_window.vacancy_deadlines.is_empty() {
+                return true;
+            }
+        }
+        !self.receiver_body_senders.is_empty()
+    }
+
+    async fn send(&mut self, bytes: Bytes) {
+        // NOTE: A failed send means the receiver has gone away
+        let _ = futures::future::join_all(
+            self.receiver_body_senders
+                .iter_mut()
+                .map(|receiver_body_sender| receiver_body_sender.sender.send(Ok(bytes.clone()))),
+        )
+        .await;
+        self.remove_gone_receivers();
+        self.n_transferred_bytes += bytes.len() as u64;
+        if let Some(replay_window) = self.replay_window.as_mut() {
+            replay_window.push(bytes);
+        }
+    }
+
+    // Remove receivers gone away, which become vacancies when they can come back
+    fn remove_gone_receivers(&mut self) {
+        let deadline = self
+            .replay_window
+            .as_ref()
+            .map(|replay_window| tokio::time::Instant::now() + replay_window.grace_period);
+        let mut n_gone_receiver_bytes = self.n_gone_receiver_bytes;
+        let mut vacancy_deadlines = Vec::new();
+        self.receiver_body_senders.retain(|receiver_body_sender| {
+            if !receiver_body_sender.is_gone() {
+                return true;
+            }
+            n_gone_receiver_bytes =
+                n_gone_receiver_bytes.max(receiver_body_sender.n_received_bytes());
+            if let Some(deadline) = deadline {
+                vacancy_deadlines.push(deadline);
+            }
+            false
+        });
+        self.n_gone_receiver_bytes = n_gone_receiver_bytes;
+        if let Some(replay_window) = self.replay_window.as_mut() {
+            replay_window
+                .vacancy_deadlines
+                .append(&mut vacancy_deadlines);
+        }
+    }
+
+    // Close receivers' bodies and wait for them to be received completely
+    // Return whether any receiver got the whole body
+    pub async fn finish(&mut self) -> bool {
+        let n_transferred_bytes = self.n_transferred_bytes;
+        let finish_waiters = self
+            .receiver_body_senders
+            .drain(..)
+            .map(|receiver_body_sender| {
+                let ReceiverBodySender {
+                    sender,
+                    finish_waiter,
+                    start,
+                    n_bytes,
+                } = receiver_body_sender;
+                // Close the body
+                drop(sender);
+                async move {
+                    let finished = matches!(finish_waiter.await, Ok(Ok(())));
+                    let n_received_bytes = start + n_bytes.load(Ordering::Relaxed);
+                    // NOTE: The body may be dropped without reaching the end after Content-Length bytes
+                    (
+                        finished || n_received_bytes == n_transferred_bytes,
+                        n_received_bytes,
+                    )
+                }
+            })
+            .collect::<Vec<_>>();
+        let mut received = false;
+        for (finished, n_received_bytes) in futures::future::join_all(finish_waiters).await {
+            if finished {
+                received = true;
+            } else {
+                self.n_gone_receiver_bytes = self.n_gone_receiver_bytes.max(n_received_bytes);
+            }
+        }
+        return received;
+    }
+
+    // Tell receivers the error and close their bodies
+    pub async fn abort(&mut self, kind: std::io::ErrorKind, message: &str) {
+        futures::future::join_all(self.receiver_body_senders.drain(..).map(
+            |mut receiver_body_sender| async move {
+                let _ = receiver_body_sender
+                    .sender
+                    .send(Err(std::io::Error::new(kind, message)))
+                    .await;
+            },
+        ))
+        .await;
+    }
+
+    fn rejoin(&mut self, rejoin_request: RejoinRequest) {
+        if self.replay_window.is_none() {
+            return;
+        }
+        // Receivers already closed become vacancies
+        self.remove_gone_receivers();
+        let replay_window = self.replay_window.as_mut().unwrap();
+        replay_window.expire_vacancies();
+        if replay_window.vacancy_deadlines.is_empty() {
+            let res = Response::builder()
+                .status(400)
+                .header("Content-Type", "text/plain")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from(format!(
+                    "[ERROR] No disconnected receiver to resume on '{}'.\n",
+                    replay_window.path
+                )))
+                .unwrap();
+            let _ = rejoin_request.res_sender.send(res);
+            return;
+        }
+        // NOTE: 206 needs Content-Range with the complete length
+        let complete_length = match self.complete_length {
+            Some(complete_length) => complete_length,
+            None => {
+                let res = Response::builder()
+                    .status(416)
+                    .header("Content-Type", "text/plain")
+                    .header("Access-Control-Allow-Origin", "*")
+                    .body(Body::from(format!(
+                        "[ERROR] Range is not supported without Content-Length of the sender on '{}'.\n",
+                        replay_window.path
+                    )))
+                    .unwrap();
+                let _ = rejoin_request.res_sender.send(res);
+                return;
+            }
+        };
+        let window_start = self.n_transferred_bytes - replay_window.n_bytes as u64;
+        if rejoin_request.start < window_start
+            || self.n_transferred_bytes < rejoin_request.start
+            || complete_length <= rejoin_request.start
+        {
+            let res = Response::builder()
+                .status(416)
+                .header("Content-Type", "text/plain")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from(format!(
+                    "[ERROR] Range should start between {} and {}, but {}.\n",
+                    window_start, self.n_transferred_bytes, rejoin_request.start
+                )))
+                .unwrap();
+            let _ = rejoin_request.res_sender.send(res);
+            return;
+        }
+        // Collect bytes from the start in the window
+        let mut skip = (rejoin_request.start - window_start) as usize;
+        let mut replay_chunks: Vec<Result<Bytes, std::io::Error>> = Vec::new();
+        for chunk in &replay_window.chunks {
+            if chunk.len() <= skip {
+                skip -= chunk.len();
+                continue;
+            }
+            replay_chunks.push(Ok(chunk.slice(skip..)));
+            skip = 0;
+        }
+        let (receiver_body_sender, receiver_body) =
+            receiver_body_channel(rejoin_request.start, replay_chunks);
+        let mut res = Response::builder().status(206).body(receiver_body).unwrap();
+        *res.headers_mut() = replay_window.receiver_res_headers.clone();
+        res.headers_mut().insert(
+            "Content-Length",
+            http::HeaderValue::from(complete_length - rejoin_request.start),
+        );
+        res.headers_mut().insert(
+            "Content-Range",
+            http::HeaderValue::from_str(&format!(
+                "bytes {}-{}/{}",
+                rejoin_request.start,
+                complete_length - 1,
+                complete_length
+            ))
+            .unwrap(),
+        );
+        if rejoin_request.res_sender.send(res).is_ok() {
+            log::info!(
+                "Receiver rejoin: '{}' from {} bytes",
+                replay_window.path,
+                rejoin_request.start
+            );
+            // Take the earliest vacancy
+            replay_window.vacancy_deadlines.sort();
+            replay_window.vacancy_deadlines.remove(0);
+            self.receiver_body_senders.push(receiver_body_sender);
+        }
+    }
+
+    // Wait for a receiver coming back until all vacancies are expired
+    async fn wait_for_rejoin(&mut self) {
+        let replay_window = match self.replay_window.as_mut() {
+            Some(replay_window) => replay_window,
+            None => return,
+        };
+        let deadline = match replay_window.vacancy_deadlines.iter().max() {
+            Some(deadline) => *deadline,
+            None => return,
+        };
+        let sleep = Box::pin(tokio::time::sleep_until(deadline));
+        if let Either::Left((Some(rejoin_request), _)) =
+            futures::future::select(replay_window.rejoin_request_receiver.next(), sleep).await
+        {
+            self.rejoin(rejoin_request);
+        }
+    }
+}
+
+// Copy each chunk of the stream to all receivers' bodies
+// NOTE: A receiver gone away is just removed and the others keep receiving
+pub async fn tee_stream<S>(mut stream: S, relay: &mut Relay) -> TeeEnd
+where
+    S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin,
+{
+    loop {
+        if !relay.has_receivers() {
+            return TeeEnd::ReceiversGone;
+        }
+        // Stop reading the stream while all receivers are away
+        if relay.receiver_body_senders.is_empty() {
+            relay.wait_for_rejoin().await;
+            continue;
+        }
+        let rejoin_request = match relay.replay_window.as_mut() {
+            Some(replay_window) => Either::Left(replay_window.rejoin_request_receiver.next()),
+            None => Either::Right(futures::future::pending()),
+        };
+        let woken = match futures::future::select(
+            stream.next(),
+            futures::future::select(
+                rejoin_request,
+                Box::pin(wait_for_receiver_gone(&mut relay.receiver_body_senders)),
+            ),
+        )
+        .await
+        {
+            Either::Left((item, _)) => Either::Left(item),
+            Either::Right((Either::Left((rejoin_request, _)), _)) => Either::Right(rejoin_request),
+            // A receiver has gone away
+            Either::Right((Either::Right(_), _)) => Either::Right(None),
+        };
+        let item = match woken {
+            Either::Left(item) => item,
+            Either::Right(Some(rejoin_request)) => {
+                relay.rejoin(rejoin_request);
+                continue;
+            }
+            Either::Right(None) => {
+                relay.remove_gone_receivers();
+                continue;
+            }
+        };
+        match item {
+            Some(Ok(bytes)) => {
+                let n_bytes = relay.n_transferred_bytes + bytes.len() as u64;
+                if matches!(relay.declared_end, Some(end) if n_bytes > end) {
+                    return TeeEnd::Oversized { n_bytes };
+                }
+                relay.send(bytes).await
+            }
+            Some(Err(err)) => return TeeEnd::SenderError(err),
+            None => return TeeEnd::Finished,
+        }
+    }
+}
+
+// Wait until a receiver goes away
+async fn wait_for_receiver_gone(receiver_body_senders: &mut [ReceiverBodySender]) {
+    if receiver_body_senders.is_empty() {
+        return futures::future::pending().await;
+    }
+    // NOTE: The finish waiters are not resolved successfully before the end of the stream
+    let _ = futures::future::select_all(
+        receiver_body_senders
+            .iter_mut()
+            .map(|receiver_body_sender| &mut receiver_body_sender.finish_waiter),
+    )
+    .await;
+}
@@ -5,16 +5,17 @@ use http::{Request, Response};
 use hyper::Body;
 
 // NOTE: futures::future::Map<..., oneshot::Receiver, ...> can be a Future
+#[allow(clippy::type_complexity)]
 pub fn req_res_handler<Fut>(
     mut handler: impl FnMut(Request<Body>, oneshot::Sender<Response<Body>>) -> Fut,
-) -> impl (FnMut(
+) -> impl FnMut(
     Request<Body>,
 ) -> futures::future::Map<
     futures::future::Join<Fut, oneshot::Receiver<Response<Body>>>,
     fn(
         ((), Result<Response<Body>, oneshot::Canceled>),
     ) -> Result<Response<Body>, oneshot::Canceled>,
->)
+>
 where
     Fut: Future<Output = ()>,
 {
@@ -0,0 +1,216 @@
+use futures::stream::StreamExt;
+use http::{Request, Response};
+use hyper::body::Bytes;
+use hyper::Body;
+
+pub const RPC_QUERY_PARAMETER_NAME: &str = "rpc";
+// "?rpc=worker": wait for a client's request
+pub const WORKER_ROLE: &str = "worker";
+// "?rpc=reply": send back the response to the client
+pub const REPLY_ROLE: &str = "reply";
+pub const RPC_ID_HEADER_NAME: &str = "x-piping-rpc-id";
+
+// Max size of the head of worker's reply
+const MAX_REPLY_HEAD_SIZE: usize = 64 * 1024;
+
+// Headers for a single connection, which are not relayed because the body is framed by each connection
+const HOP_BY_HOP_HEADER_NAMES: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+fn is_hop_by_hop_header(name: &str) -> bool {
+    return HOP_BY_HOP_HEADER_NAMES
+        .iter()
+        .any(|hop_by_hop_name| name.eq_ignore_ascii_case(hop_by_hop_name));
+}
+
+// Serialize client's request into "message/http"
+pub fn serialize_request(req: Request<Body>) -> Body {
+    let (parts, body) = req.into_parts();
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\n",
+        parts.method,
+        parts
+            .uri
+            .path_and_query()
+            .map(|path_and_query| path_and_query.as_str())
+            .unwrap_or("/")
+    )
+    .into_bytes();
+    for (name, value) in parts.headers.iter() {
+        if is_hop_by_hop_header(name.as_str()) {
+            continue;
+        }
+        head.extend_from_slice(name.as_str().as_bytes());
+        head.extend_from_slice(b": ");
+        head.extend_from_slice(value.as_bytes());
+        head.extend_from_slice(b"\r\n");
+    }
+    head.extend_from_slice(b"\r\n");
+    return Body::wrap_stream(
+        futures::stream::once(futures::future::ok::<_, hyper::Error>(Bytes::from(head)))
+            .chain(body),
+    );
+}
+
+// Parse worker's reply in "message/http" into the response to the client
+pub async fn parse_reply(mut body: Body) -> Result<Response<Body>, String> {
+    let mut buf: Vec<u8> = Vec::new();
+    loop {
+        match body.next().await {
+            Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+            Some(Err(err)) => return Err(format!("Failed to read the reply: {}", err)),
+            None => return Err("The reply ended before its head.".to_owned()),
+        }
+        let mut headers = [httparse::EMPTY_HEADER; 64];
+        let mut parsed = httparse::Response::new(&mut headers);
+        let head_size = match parsed.parse(&buf) {
+            Ok(httparse::Status::Complete(head_size)) => head_size,
+            Ok(httparse::Status::Partial) => {
+                if buf.len() > MAX_REPLY_HEAD_SIZE {
+                    return Err("The head of the reply is too large.".to_owned());
+                }
+                continue;
+            }
+            Err(err) => return Err(format!("Invalid reply: {}", err)),
+        };
+        let mut chunked = false;
+        for header in parsed.headers.iter() {
+            if !header.name.eq_ignore_ascii_case("transfer-encoding") {
+                continue;
+            }
+            let value = String::from_utf8_lossy(header.value);
+            // NOTE: Only "chunked" is decoded, and other codings such as "gzip" are not
+            let is_chunked = value.split(',').map(|coding| coding.trim()).all(|coding| {
+                return coding.is_empty() || coding.eq_ignore_ascii_case("chunked");
+            });
+            if !is_chunked {
+                return Err(format!("Unsupported Transfer-Encoding: {}", value));
+            }
+            chunked = true;
+        }
+        let mut builder = Response::builder().status(parsed.code.unwrap_or(200));
+        for header in parsed.headers.iter() {
+            if is_hop_by_hop_header(header.name) {
+                continue;
+            }
+            // Content-Length is ignored when the body is chunked
+            if chunked && header.name.eq_ignore_ascii_case("content-length") {
+                continue;
+            }
+            builder = builder.header(header.name, header.value);
+        }
+        let rest = buf.split_off(head_size);
+        let body = if chunked {
+            Body::wrap_stream(dechunk(rest, body))
+        } else {
+            Body::wrap_stream(
+                futures::stream::once(futures::future::ok::<_, hyper::Error>(Bytes::from(rest)))
+                    .chain(body),
+            )
+        };
+        return builder
+            .body(body)
+            .map_err(|err| format!("Invalid reply: {}", err));
+    }
+}
+
+// Max size of a chunk-size line or a trailer line in the chunked reply
+const MAX_CHUNK_LINE_SIZE: usize = 4 * 1024;
+
+enum DechunkState {
+    Size,
+    Data(usize),
+    DataEnd,
+    Trailer,
+    Done,
+}
+
+fn invalid_chunk(message: &str) -> std::io::Error {
+    return std::io::Error::new(std::io::ErrorKind::InvalidData, message.to_owned());
+}
+
+// Decode the reply body in chunked transfer coding
+fn dechunk(buf: Vec<u8>, body: Body) -> impl futures::Stream<Item = Result<Bytes, std::io::Error>> {
+    return futures::stream::unfold(
+        (buf, body, DechunkState::Size),
+        |(mut buf, mut body, mut state)| async move {
+            loop {
+                match state {
+                    DechunkState::Done => return None,
+                    DechunkState::Data(remaining) if !buf.is_empty() => {
+                        let size = remaining.min(buf.len());
+                        let rest = buf.split_off(size);
+                        let data = Bytes::from(std::mem::replace(&mut buf, rest));
+                        state = if size == remaining {
+                            DechunkState::DataEnd
+                        } else {
+                            DechunkState::Data(remaining - size)
+                        };
+                        return Some((Ok(data), (buf, body, state)));
+                    }
+                    DechunkState::DataEnd if buf.len() >= 2 => {
+                        if &buf[..2] != b"\r\n" {
+                            let err = invalid_chunk("No CRLF after chunk data");
+                            return Some((Err(err), (buf, body, DechunkState::Done)));
+                        }
+                        buf.drain(..2);
+                        state = DechunkState::Size;
+                        continue;
+                    }
+                    DechunkState::Size | DechunkState::Trailer => {
+                        if let Some(line_end) = buf.windows(2).position(|w| w == b"\r\n") {
+                            let line = String::from_utf8_lossy(&buf[..line_end]).into_owned();
+                            buf.drain(..line_end + 2);
+                            if let DechunkState::Trailer = state {
+                                // An empty line ends the trailer
+                                if line.is_empty() {
+                                    state = DechunkState::Done;
+                                }
+                                continue;
+                            }
+                            // NOTE: Chunk extensions after ';' are ignored
+                            let size_str = line.split(';').next().unwrap_or("").trim();
+                            state = match usize::from_str_radix(size_str, 16) {
+                                Ok(0) => DechunkState::Trailer,
+                                Ok(size) => DechunkState::Data(size),
+                                Err(_) => {
+                                    let err = invalid_chunk("Invalid chunk size");
+                                    return Some((Err(err), (buf, body, DechunkState::Done)));
+                                }
+                            };
+                            continue;
+                        }
+                        if buf.len() > MAX_CHUNK_LINE_SIZE {
+                            let err = invalid_chunk("Too long chunk line");
+                            return Some((Err(err), (buf, body, DechunkState::Done)));
+                        }
+                    }
+                    _ => {}
+                }
+                // Read more because buffered bytes are not enough
+                match body.next().await {
+                    Some(Ok(chunk)) => buf.extend_from_slice(&chunk),
+                    Some(Err(err)) => {
+                        let err = std::io::Error::new(std::io::ErrorKind::Other, err);
+                        return Some((Err(err), (buf, body, DechunkState::Done)));
+                    }
+                    None => {
+                        let err = std::io::Error::new(
+                            std::io::ErrorKind::UnexpectedEof,
+                            "The chunked reply ended before its last chunk",
+                        );
+                        return Some((Err(err), (buf, body, DechunkState::Done)));
+                    }
+                }
+            }
+        },
+    );
+}
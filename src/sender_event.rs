@@ -0,0 +1,146 @@
+use hyper::body::Bytes;
+use serde::Serialize;
+
+// Notification to sender through its response body
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SenderEvent {
+    Queued { n_senders_ahead: u32 },
+    Waiting { n_receivers: u32 },
+    ReceiversConnected { n_receivers: u32 },
+    ReceiverConnected,
+    Start { n_receivers: u32 },
+    Resume { start: u64 },
+    Progress { n_bytes: u64 },
+    Finished { n_bytes: u64 },
+    Error { message: String },
+}
+
+// Event with its context in machine-readable formats
+#[derive(Serialize)]
+struct SenderEventEnvelope<'a> {
+    #[serde(flatten)]
+    event: &'a SenderEvent,
+    path: &'a str,
+    // Milliseconds since UNIX epoch
+    timestamp: u64,
+}
+
+pub const FORMAT_QUERY_PARAMETER_NAME: &str = "format";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SenderResFormat {
+    Text,
+    EventStream,
+    Ndjson,
+}
+
+impl SenderResFormat {
+    // Choose format by "?format=json" or Accept header
+    pub fn negotiate(format_query: Option<&String>, accept: Option<&http::HeaderValue>) -> Self {
+        if let Some(format) = format_query {
+            if format == "json" || format == "ndjson" {
+                return SenderResFormat::Ndjson;
+            }
+        }
+        let accept = accept.and_then(|value| value.to_str().ok()).unwrap_or("");
+        // NOTE: "application/json" is not matched because clients such as axios accept it by default
+        for media_range in accept.split(',') {
+            let mut params = media_range.split(';');
+            let media_type = params.next().unwrap_or("").trim();
+            // Media ranges with "q=0" are refused
+            let refused = params.any(|param| {
+                let param = param.trim();
+                return param.starts_with("q=0")
+                    && param[3..]
+                        .trim_start_matches('.')
+                        .bytes()
+                        .all(|b| b == b'0');
+            });
+            if refused {
+                continue;
+            }
+            if media_type.eq_ignore_ascii_case("text/event-stream") {
+                return SenderResFormat::EventStream;
+            }
+            if media_type.eq_ignore_ascii_case("application/x-ndjson") {
+                return SenderResFormat::Ndjson;
+            }
+        }
+        SenderResFormat::Text
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            SenderResFormat::Text => "text/plain",
+            SenderResFormat::EventStream => "text/event-stream",
+            SenderResFormat::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn reports_progress(&self) -> bool {
+        *self != SenderResFormat::Text
+    }
+
+    pub fn render(&self, path: &str, event: &SenderEvent) -> Bytes {
+        let to_json = || {
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|duration| duration.as_millis() as u64)
+                .unwrap_or(0);
+            serde_json::to_string(&SenderEventEnvelope {
+                event,
+                path,
+                timestamp,
+            })
+            .unwrap()
+        };
+        match self {
+            SenderResFormat::Text => Bytes::from(text_message(event)),
+            SenderResFormat::EventStream => {
+                Bytes::from(format!("event: {}\ndata: {}\n\n", event.name(), to_json()))
+            }
+            SenderResFormat::Ndjson => Bytes::from(format!("{}\n", to_json())),
+        }
+    }
+}
+
+impl SenderEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SenderEvent::Queued { .. } => "queued",
+            SenderEvent::Waiting { .. } => "waiting",
+            SenderEvent::ReceiversConnected { .. } => "receivers_connected",
+            SenderEvent::ReceiverConnected => "receiver_connected",
+            SenderEvent::Start { .. } => "start",
+            SenderEvent::Resume { .. } => "resume",
+            SenderEvent::Progress { .. } => "progress",
+            SenderEvent::Finished { .. } => "finished",
+            SenderEvent::Error { .. } => "error",
+        }
+    }
+}
+
+fn text_message(event: &SenderEvent) -> String {
+    match event {
+        SenderEvent::Queued { n_senders_ahead } => {
+            format!("[INFO] Queued behind {} sender(s)...\n", n_senders_ahead)
+        }
+        SenderEvent::Waiting { n_receivers } => {
+            format!("[INFO] Waiting for {} receiver(s)...\n", n_receivers)
+        }
+        SenderEvent::ReceiversConnected { n_receivers } => format!(
+            "[INFO] {} receiver(s) has/have been connected.\n",
+            n_receivers
+        ),
+        SenderEvent::ReceiverConnected => "[INFO] A receiver was connected.\n".to_owned(),
+        SenderEvent::Start { n_receivers } => {
+            format!("[INFO] Start sending to {} receiver(s)...\n", n_receivers)
+        }
+        SenderEvent::Resume { start } => format!("[INFO] Resume sending from {} bytes...\n", start),
+        // Progress is too noisy for text
+        SenderEvent::Progress { .. } => "".to_owned(),
+        SenderEvent::Finished { .. } => "[INFO] Sent successfully!\n".to_owned(),
+        SenderEvent::Error { message } => format!("[ERROR] {}\n", message),
+    }
+}
@@ -0,0 +1,201 @@
+use futures::stream::{Stream, StreamExt};
+use http::Response;
+use hyper::body::Bytes;
+use hyper::Body;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::util::{generate_token, RemoveOnDrop};
+
+const FILE_EXTENSION: &str = "piping-mail";
+const READ_BUF_SIZE: usize = 64 * 1024;
+
+struct Mail {
+    id: String,
+    file_path: PathBuf,
+    res_headers: http::HeaderMap,
+    size: u64,
+    // Dropped on taking the mail to stop waiting for its expiry
+    _expiry_canceler: futures::channel::oneshot::Sender<()>,
+}
+
+enum MailState {
+    Storing,
+    Stored(Mail),
+}
+
+pub enum StoreError {
+    // Another mail is stored on the path
+    Exists,
+    TooLarge { max_size: u64 },
+    Io(std::io::Error),
+    Body(hyper::Error),
+}
+
+// Senders' bodies spooled on disk until receivers come
+pub struct Storage {
+    dir: PathBuf,
+    ttl: std::time::Duration,
+    max_size: u64,
+    path_to_mail: Arc<Mutex<HashMap<String, MailState>>>,
+}
+
+impl Storage {
+    pub fn new(dir: PathBuf, ttl: std::time::Duration, max_size: u64) -> Self {
+        // NOTE: Mails are forgotten on restart, so their files are removed
+        if let Ok(entries) = std::fs::read_dir(&dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension() == Some(std::ffi::OsStr::new(FILE_EXTENSION)) {
+                    let _ = std::fs::remove_file(path);
+                }
+            }
+        }
+        Storage {
+            dir,
+            ttl,
+            max_size,
+            path_to_mail: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn ttl(&self) -> std::time::Duration {
+        self.ttl
+    }
+
+    // Spool the body on the path and return its size
+    pub async fn store<S>(
+        &self,
+        path: &str,
+        res_headers: http::HeaderMap,
+        body: S,
+    ) -> Result<u64, StoreError>
+    where
+        S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin,
+    {
+        {
+            let mut path_to_mail = self.path_to_mail.lock().unwrap();
+            if path_to_mail.contains_key(path) {
+                return Err(StoreError::Exists);
+            }
+            path_to_mail.insert(path.to_string(), MailState::Storing);
+        }
+        let id = generate_token();
+        let file_path = self.dir.join(format!("{}.{}", id, FILE_EXTENSION));
+        let result = self.write_file(&file_path, body).await;
+        let mut path_to_mail = self.path_to_mail.lock().unwrap();
+        let size = match result {
+            Ok(size) => size,
+            Err(err) => {
+                path_to_mail.remove(path);
+                let _ = std::fs::remove_file(&file_path);
+                return Err(err);
+            }
+        };
+        let (expiry_canceler, expiry_cancel_waiter) = futures::channel::oneshot::channel::<()>();
+        path_to_mail.insert(
+            path.to_string(),
+            MailState::Stored(Mail {
+                id: id.clone(),
+                file_path,
+                res_headers,
+                size,
+                _expiry_canceler: expiry_canceler,
+            }),
+        );
+        // Remove the mail after TTL
+        let path_to_mail = Arc::clone(&self.path_to_mail);
+        let path = path.to_string();
+        let ttl = self.ttl;
+        tokio::spawn(async move {
+            // The mail has been taken before TTL
+            if let futures::future::Either::Right(_) =
+                futures::future::select(Box::pin(tokio::time::sleep(ttl)), expiry_cancel_waiter)
+                    .await
+            {
+                return;
+            }
+            let mut path_to_mail = path_to_mail.lock().unwrap();
+            if let Some(MailState::Stored(mail)) = path_to_mail.get(&path) {
+                if mail.id == id {
+                    log::info!("Mail expired: '{}'", path);
+                    let _ = std::fs::remove_file(&mail.file_path);
+                    path_to_mail.remove(&path);
+                }
+            }
+        });
+        return Ok(size);
+    }
+
+    async fn write_file<S>(&self, file_path: &PathBuf, mut body: S) -> Result<u64, StoreError>
+    where
+        S: Stream<Item = Result<Bytes, hyper::Error>> + Unpin,
+    {
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .map_err(StoreError::Io)?;
+        let mut file = tokio::fs::File::create(file_path)
+            .await
+            .map_err(StoreError::Io)?;
+        let mut size: u64 = 0;
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(StoreError::Body)?;
+            size += chunk.len() as u64;
+            if size > self.max_size {
+                return Err(StoreError::TooLarge {
+                    max_size: self.max_size,
+                });
+            }
+            file.write_all(&chunk).await.map_err(StoreError::Io)?;
+        }
+        file.flush().await.map_err(StoreError::Io)?;
+        return Ok(size);
+    }
+
+    // Take the mail on the path as a response which removes the file at the end
+    pub fn take(&self, path: &str) -> Option<Response<Body>> {
+        let mail = {
+            let mut path_to_mail = self.path_to_mail.lock().unwrap();
+            match path_to_mail.remove(path) {
+                Some(MailState::Stored(mail)) => mail,
+                Some(storing) => {
+                    path_to_mail.insert(path.to_string(), storing);
+                    return None;
+                }
+                None => return None,
+            }
+        };
+        // Remove the spooled file when the receiver finishes or leaves
+        let remove_on_drop = RemoveOnDrop(mail.file_path.clone());
+        // NOTE: The file is opened on the first read so that the mail is taken at once
+        let file: Option<tokio::fs::File> = None;
+        let body = futures::stream::unfold(
+            (file, remove_on_drop),
+            |(file, remove_on_drop)| async move {
+                let mut file = match file {
+                    Some(file) => file,
+                    None => match tokio::fs::File::open(&remove_on_drop.0).await {
+                        Ok(file) => file,
+                        Err(err) => return Some((Err(err), (None, remove_on_drop))),
+                    },
+                };
+                let mut buf = vec![0u8; READ_BUF_SIZE];
+                match file.read(&mut buf).await {
+                    Ok(0) => None,
+                    Ok(n) => {
+                        buf.truncate(n);
+                        Some((Ok(Bytes::from(buf)), (Some(file), remove_on_drop)))
+                    }
+                    Err(err) => Some((Err(err), (Some(file), remove_on_drop))),
+                }
+            },
+        );
+        let mut res = Response::new(Body::wrap_stream(body));
+        *res.headers_mut() = mail.res_headers;
+        res.headers_mut()
+            .insert("Content-Length", http::HeaderValue::from(mail.size));
+        return Some(res);
+    }
+}
@@ -5,6 +5,7 @@ use futures::channel::oneshot;
 use futures::ready;
 use pin_project_lite::pin_project;
 use std::ops::Deref;
+use std::sync::atomic::AtomicU64;
 use std::sync::{Arc, RwLock};
 use tokio::net::TcpStream;
 use tokio_rustls::server::TlsStream;
@@ -66,11 +67,23 @@ pin_project! {
     pub struct FinishDetectableStream<S> {
         #[pin]
         stream_pin: S,
-        finish_notifier: Option<oneshot::Sender<()>>,
+        finish_notifier: Option<oneshot::Sender<Result<(), String>>>,
+        n_bytes: Arc<AtomicU64>,
     }
 }
 
-impl<S: futures::stream::Stream> futures::stream::Stream for FinishDetectableStream<S> {
+impl<S> FinishDetectableStream<S> {
+    // Counter of bytes which have passed through the stream
+    pub fn n_bytes(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.n_bytes)
+    }
+}
+
+impl<S, E> futures::stream::Stream for FinishDetectableStream<S>
+where
+    S: futures::stream::Stream<Item = Result<hyper::body::Bytes, E>>,
+    E: std::fmt::Display,
+{
     type Item = S::Item;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
@@ -80,32 +93,176 @@ impl<S: futures::stream::Stream> futures::stream::Stream for FinishDetectableStr
             Poll::Ready(None) => {
                 // Notify finish
                 if let Some(notifier) = this.finish_notifier.take() {
-                    notifier.send(()).unwrap();
+                    let _ = notifier.send(Ok(()));
                 }
                 Poll::Ready(None)
             }
-            poll => poll,
+            Poll::Ready(Some(Ok(bytes))) => {
+                this.n_bytes
+                    .fetch_add(bytes.len() as u64, std::sync::atomic::Ordering::Relaxed);
+                Poll::Ready(Some(Ok(bytes)))
+            }
+            Poll::Ready(Some(Err(err))) => {
+                // Notify error
+                if let Some(notifier) = this.finish_notifier.take() {
+                    let _ = notifier.send(Err(err.to_string()));
+                }
+                Poll::Ready(Some(Err(err)))
+            }
+            Poll::Pending => Poll::Pending,
         }
     }
 }
 
 pub fn finish_detectable_stream<S>(
     stream: S,
-) -> (FinishDetectableStream<S>, oneshot::Receiver<()>) {
-    let (finish_notifier, finish_waiter) = oneshot::channel::<()>();
+) -> (
+    FinishDetectableStream<S>,
+    oneshot::Receiver<Result<(), String>>,
+) {
+    let (finish_notifier, finish_waiter) = oneshot::channel::<Result<(), String>>();
     (
         FinishDetectableStream {
             stream_pin: stream,
             finish_notifier: Some(finish_notifier),
+            n_bytes: Arc::new(AtomicU64::new(0)),
         },
         finish_waiter,
     )
 }
 
+pin_project! {
+    pub struct DropDetectableStream<S> {
+        #[pin]
+        stream_pin: S,
+        _drop_notifier: oneshot::Sender<()>,
+    }
+}
+
+impl<S: futures::stream::Stream> futures::stream::Stream for DropDetectableStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.project().stream_pin.poll_next(cx)
+    }
+}
+
+// The waiter is canceled when the stream is dropped (e.g. the client has gone away)
+pub fn drop_detectable_stream<S>(stream: S) -> (DropDetectableStream<S>, oneshot::Receiver<()>) {
+    let (drop_notifier, drop_waiter) = oneshot::channel::<()>();
+    (
+        DropDetectableStream {
+            stream_pin: stream,
+            _drop_notifier: drop_notifier,
+        },
+        drop_waiter,
+    )
+}
+
+// Content-Range of request (e.g. "bytes 0-99/1000", "bytes 100-199/*")
+#[derive(Debug, PartialEq, Eq)]
+pub struct ContentRange {
+    pub start: u64,
+    pub end: u64,
+    pub complete_length: Option<u64>,
+}
+
+pub fn parse_content_range(value: &str) -> Option<ContentRange> {
+    let range_and_length = value.trim().strip_prefix("bytes ")?;
+    let (range, complete_length) = range_and_length.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    let start: u64 = start.trim().parse().ok()?;
+    let end: u64 = end.trim().parse().ok()?;
+    let complete_length: Option<u64> = match complete_length.trim() {
+        "*" => None,
+        len => Some(len.parse().ok()?),
+    };
+    if end < start || matches!(complete_length, Some(len) if end >= len) {
+        return None;
+    }
+    Some(ContentRange {
+        start,
+        end,
+        complete_length,
+    })
+}
+
+// Get the start of Range of request only in the form "bytes=<start>-"
+pub fn parse_range_start(value: &str) -> Option<u64> {
+    value
+        .trim()
+        .strip_prefix("bytes=")?
+        .strip_suffix('-')?
+        .trim()
+        .parse()
+        .ok()
+}
+
+// Get the file name in Content-Disposition of a multipart field (e.g. `form-data; name="f"; filename="a.png"`)
+// NOTE: Browsers send a non-ASCII file name in raw UTF-8
+pub fn get_multipart_filename(content_disposition: &http::HeaderValue) -> Option<String> {
+    let value = String::from_utf8_lossy(content_disposition.as_bytes());
+    let mut rest: &str = &value;
+    while let Some(index) = rest.find("filename=") {
+        let is_param = rest[..index].trim_end().ends_with(';');
+        rest = &rest[index + "filename=".len()..];
+        if !is_param {
+            continue;
+        }
+        let filename = match rest.strip_prefix('"') {
+            Some(quoted) => &quoted[..quoted.find('"')?],
+            None => rest.split(';').next().unwrap_or("").trim(),
+        };
+        return Some(filename.to_string());
+    }
+    return None;
+}
+
+// Content-Disposition to download as the file name, with RFC 5987 "filename*" for a non-ASCII one
+pub fn attachment_content_disposition(filename: &str) -> http::HeaderValue {
+    let is_plain = |c: char| c.is_ascii() && !c.is_ascii_control() && c != '"' && c != '\\';
+    // NOTE: The fallback is for clients not supporting "filename*"
+    let fallback: String = filename
+        .chars()
+        .map(|c| if is_plain(c) { c } else { '_' })
+        .collect();
+    let mut value = format!("attachment; filename=\"{}\"", fallback);
+    if !filename.chars().all(is_plain) {
+        value.push_str("; filename*=UTF-8''");
+        for byte in filename.bytes() {
+            // attr-char of RFC 5987
+            if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+                value.push(byte as char);
+            } else {
+                value.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+    return http::HeaderValue::from_str(&value).unwrap();
+}
+
 pub fn make_io_error(err: String) -> std::io::Error {
     std::io::Error::new(std::io::ErrorKind::Other, err)
 }
 
+// Remove the file when dropped
+pub struct RemoveOnDrop(pub std::path::PathBuf);
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// Random hex string hard to guess
+pub fn generate_token() -> String {
+    use rand::Rng;
+    let mut rng = rand::thread_rng();
+    return (0..32)
+        .map(|_| format!("{:x}", rng.gen_range(0..16)))
+        .collect();
+}
+
 // (base: https://github.com/ctz/hyper-rustls/blob/5f073724f7b5eee3a2d72f0a86094fc2718b51cd/examples/server.rs)
 pub fn load_tls_config(
     cert_path: impl AsRef<std::path::Path>,
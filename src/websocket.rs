@@ -0,0 +1,183 @@
+use futures::channel::oneshot;
+use futures::future::Either;
+use futures::sink::SinkExt;
+use futures::stream::StreamExt;
+use http::{Method, Request, Response};
+use hyper::body::Bytes;
+use hyper::Body;
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Role};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+use crate::piping_server::PipingServer;
+use crate::req_res_handler::req_res_handler;
+
+pub const ROLE_QUERY_PARAMETER_NAME: &str = "role";
+pub const SENDER_ROLE: &str = "sender";
+
+// Max bytes of the reason in a close frame, whose payload is up to 125 bytes with the 2-byte code
+const MAX_CLOSE_REASON_SIZE: usize = 123;
+
+pub fn is_websocket_upgrade(req: &Request<Body>) -> bool {
+    let has_upgrade_websocket = req
+        .headers()
+        .get("upgrade")
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.eq_ignore_ascii_case("websocket"))
+        .unwrap_or(false);
+    req.method() == Method::GET && has_upgrade_websocket
+}
+
+// Accept WebSocket and act as a sender or a receiver over it
+// NOTE: The connection is handled as a plain HTTP sender or receiver by the same handler
+pub fn handle_websocket(
+    piping_server: PipingServer,
+    uses_https: bool,
+    mut req: Request<Body>,
+    is_sender: bool,
+    res_sender: oneshot::Sender<Response<Body>>,
+) {
+    let accept_key = match req.headers().get("sec-websocket-key") {
+        Some(key) => derive_accept_key(key.as_bytes()),
+        None => {
+            let res = Response::builder()
+                .status(400)
+                .header("Content-Type", "text/plain")
+                .header("Access-Control-Allow-Origin", "*")
+                .body(Body::from("[ERROR] Sec-WebSocket-Key is missing.\n"))
+                .unwrap();
+            res_sender.send(res).unwrap();
+            return;
+        }
+    };
+    let on_upgrade = hyper::upgrade::on(&mut req);
+    let res = Response::builder()
+        .status(101)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", accept_key)
+        .body(Body::empty())
+        .unwrap();
+    res_sender.send(res).unwrap();
+
+    // Request handled as a plain HTTP sender or receiver
+    let mut inner_req_builder = Request::builder()
+        .method(if is_sender { Method::POST } else { Method::GET })
+        .uri(req.uri().clone())
+        .version(req.version());
+    for (name, value) in req.headers() {
+        if !is_websocket_handshake_header(name) {
+            inner_req_builder = inner_req_builder.header(name, value);
+        }
+    }
+
+    tokio::spawn(async move {
+        let upgraded = match on_upgrade.await {
+            Ok(upgraded) => upgraded,
+            Err(err) => {
+                log::error!("WebSocket upgrade error: {}", err);
+                return;
+            }
+        };
+        let websocket = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+        let (mut websocket_sink, websocket_stream) = websocket.split();
+        let (inner_req_body, receiver_websocket_stream) = if is_sender {
+            // Binary and text frames are relayed as the body until close
+            // NOTE: An empty binary frame also ends the body so that the sender can still get messages
+            let body = Body::wrap_stream(
+                websocket_stream
+                    .take_while(|message| {
+                        futures::future::ready(match message {
+                            Ok(Message::Close(_)) => false,
+                            Ok(Message::Binary(data)) => !data.is_empty(),
+                            _ => true,
+                        })
+                    })
+                    .filter_map(|message| {
+                        futures::future::ready(match message {
+                            Ok(Message::Binary(data)) => Some(Ok(Bytes::from(data))),
+                            Ok(Message::Text(text)) => Some(Ok(Bytes::from(text))),
+                            Ok(_) => None,
+                            Err(err) => Some(Err(err)),
+                        })
+                    }),
+            );
+            (body, None)
+        } else {
+            (Body::empty(), Some(websocket_stream))
+        };
+        let mut handler = req_res_handler(move |req, res_sender| {
+            piping_server.handler(uses_https, req, res_sender)
+        });
+        let inner_res_future = handler(inner_req_builder.body(inner_req_body).unwrap());
+        let inner_res_result = match receiver_websocket_stream {
+            Some(websocket_stream) => {
+                // NOTE: The inner request is dropped when the receiver closes the WebSocket while waiting
+                let closed = websocket_stream
+                    .filter(|message| {
+                        futures::future::ready(matches!(message, Ok(Message::Close(_)) | Err(_)))
+                    })
+                    .into_future();
+                match futures::future::select(Box::pin(inner_res_future), closed).await {
+                    Either::Left((inner_res_result, _)) => inner_res_result,
+                    Either::Right(_) => return,
+                }
+            }
+            None => inner_res_future.await,
+        };
+        let inner_res = match inner_res_result {
+            Ok(inner_res) => inner_res,
+            Err(_) => {
+                let _ = websocket_sink.send(Message::Close(None)).await;
+                return;
+            }
+        };
+        // Receiver gets data as binary frames, while messages and errors are text frames
+        let sends_binary = !is_sender && inner_res.status().is_success();
+        let mut inner_res_body = inner_res.into_body();
+        let mut close_frame: Option<CloseFrame> = None;
+        while let Some(bytes_result) = inner_res_body.next().await {
+            let bytes = match bytes_result {
+                Ok(bytes) => bytes,
+                // Tell the peer that the transfer failed instead of closing normally
+                Err(err) => {
+                    close_frame = Some(CloseFrame {
+                        code: CloseCode::Error,
+                        reason: truncate_close_reason(err.to_string()).into(),
+                    });
+                    break;
+                }
+            };
+            if bytes.is_empty() {
+                continue;
+            }
+            let message = if sends_binary {
+                Message::Binary(bytes.to_vec())
+            } else {
+                Message::Text(String::from_utf8_lossy(&bytes).into_owned())
+            };
+            if websocket_sink.send(message).await.is_err() {
+                return;
+            }
+        }
+        let _ = websocket_sink.send(Message::Close(close_frame)).await;
+    });
+}
+
+fn truncate_close_reason(mut reason: String) -> String {
+    if reason.len() > MAX_CLOSE_REASON_SIZE {
+        let mut size = MAX_CLOSE_REASON_SIZE;
+        while !reason.is_char_boundary(size) {
+            size -= 1;
+        }
+        reason.truncate(size);
+    }
+    return reason;
+}
+
+fn is_websocket_handshake_header(name: &http::header::HeaderName) -> bool {
+    let name = name.as_str();
+    name == "upgrade" || name == "connection" || name.starts_with("sec-websocket-")
+}
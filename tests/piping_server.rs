@@ -1,3 +1,6 @@
+// NOTE: The baseline test helpers are kept as they are
+#![allow(clippy::while_let_loop)]
+
 use futures::channel::oneshot;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Client;
@@ -5,7 +8,7 @@ use hyper::Server;
 use specit::tokio_it as it;
 use std::convert::Infallible;
 
-use piping_server::piping_server::PipingServer;
+use piping_server::piping_server::{PipingServer, PipingServerConfig};
 use piping_server::req_res_handler::req_res_handler;
 use std::net::SocketAddr;
 
@@ -41,7 +44,11 @@ struct Serve {
 
 // Serve Piping Server on available port
 async fn serve() -> Serve {
-    let piping_server = PipingServer::new();
+    serve_with_config(PipingServerConfig::default()).await
+}
+
+async fn serve_with_config(config: PipingServerConfig) -> Serve {
+    let piping_server = PipingServer::with_config(config);
 
     let (addr_tx, addr_rx) = oneshot::channel::<SocketAddr>();
     let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
@@ -138,7 +145,9 @@ async fn f() -> Result<(), BoxError> {
 
     let body_string = String::from_utf8(read_all_body(body).await)?;
     // Body should contain "Piping"
-    assert!(body_string.contains("action=\"mypath\""));
+    // NOTE: Files are sent in zip so that none of multiple files is dropped
+    assert!(body_string.contains("action=\"mypath?archive\""));
+    assert!(body_string.contains("multiple"));
     // Body should specify charset
     assert!(body_string
         .to_lowercase()
@@ -322,13 +331,13 @@ async fn f() -> Result<(), BoxError> {
     );
     assert_eq!(
         get_header_value(&parts.headers, "access-control-allow-methods"),
-        Some("GET, HEAD, POST, PUT, OPTIONS")
+        Some("GET, HEAD, POST, PUT, DELETE, OPTIONS")
     );
     assert_eq!(
         get_header_value(&parts.headers, "access-control-allow-headers")
             .unwrap()
             .to_lowercase(),
-        "content-type, content-disposition, x-piping".to_owned()
+        "content-type, content-disposition, x-piping, x-piping-cancel-token".to_owned()
     );
     assert_eq!(
         get_header_value(&parts.headers, "access-control-max-age"),
@@ -369,7 +378,7 @@ async fn f() -> Result<(), BoxError> {
     Ok(())
 }
 
-#[it("should reject POST and PUT with Content-Range")]
+#[it("should reject POST and PUT with Content-Range when no transfer is interrupted")]
 async fn f() -> Result<(), BoxError> {
     let serve: Serve = serve().await;
 
@@ -544,116 +553,2391 @@ async fn f() -> Result<(), BoxError> {
 
 // TODO: add tests when sender or receiver receive 400
 
-#[it("should pass X-Piping and attach Access-Control-Expose-Headers: X-Piping when sending with X-Piping")]
+#[it("should handle a receiver reconnecting after aborting while waiting")]
 async fn f() -> Result<(), BoxError> {
+    use tokio::io::AsyncWriteExt;
+
     let serve: Serve = serve().await;
 
+    // Receiver aborting before sender comes
+    let mut aborted_stream = tokio::net::TcpStream::connect(serve.addr).await?;
+    aborted_stream
+        .write_all(b"GET /mypath HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await?;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    drop(aborted_stream);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
     let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res_future = client.request(get_req);
 
-    let send_body_str = "this is a content";
     let send_req = hyper::Request::builder()
         .method(hyper::Method::POST)
-        .header("Content-Type", "text/plain")
-        .header("X-Piping", "mymetadata")
         .uri(uri.clone())
-        .body(hyper::Body::from(send_body_str))?;
+        .body(hyper::Body::from("this is a content"))?;
+    let _send_res = client.request(send_req).await?;
+
+    let (get_res_parts, get_res_body) = get_res_future.await?.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::OK);
+    assert_eq!(
+        read_all_body(get_res_body).await,
+        "this is a content".as_bytes().to_vec()
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should handle a sender reconnecting after aborting while waiting")]
+async fn f() -> Result<(), BoxError> {
+    use tokio::io::AsyncWriteExt;
 
+    let serve: Serve = serve().await;
+
+    // Sender aborting before receiver comes
+    let mut aborted_stream = tokio::net::TcpStream::connect(serve.addr).await?;
+    aborted_stream
+        .write_all(b"POST /mypath HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello")
+        .await?;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    drop(aborted_stream);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
     let client = Client::new();
-    let send_res = client.request(send_req).await?;
-    let (send_res_parts, _send_res_body) = send_res.into_parts();
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("this is a content"))?;
+    let (send_res_parts, _send_res_body) = client.request(send_req).await?.into_parts();
     assert_eq!(send_res_parts.status, http::StatusCode::OK);
 
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let (_, get_res_body) = client.request(get_req).await?.into_parts();
+    assert_eq!(
+        read_all_body(get_res_body).await,
+        "this is a content".as_bytes().to_vec()
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should hold back 100 Continue until a receiver connects")]
+async fn f() -> Result<(), BoxError> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let serve: Serve = serve().await;
+
+    let mut send_stream = tokio::net::TcpStream::connect(serve.addr).await?;
+    send_stream
+        .write_all(b"POST /mypath HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n")
+        .await?;
+
+    // Nothing should be written to the sender while waiting
+    let mut buf = [0u8; 1024];
+    let read_while_waiting = tokio::time::timeout(
+        std::time::Duration::from_millis(200),
+        send_stream.read(&mut buf),
+    )
+    .await;
+    assert!(read_while_waiting.is_err());
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
     let get_req = hyper::Request::builder()
         .method(hyper::Method::GET)
         .uri(uri.clone())
         .body(hyper::Body::empty())?;
     let client = Client::new();
-    let (parts, body) = client.request(get_req).await?.into_parts();
+    let get_res_future = tokio::spawn(client.request(get_req));
 
-    let all_bytes: Vec<u8> = read_all_body(body).await;
+    let n = send_stream.read(&mut buf).await?;
+    assert!(buf[..n].starts_with(b"HTTP/1.1 "));
+    send_stream.write_all(b"hello").await?;
 
-    let expect = send_body_str.to_owned().into_bytes();
-    assert_eq!(all_bytes, expect);
+    let (_, get_res_body) = get_res_future.await??.into_parts();
+    assert_eq!(
+        read_all_body(get_res_body).await,
+        "hello".as_bytes().to_vec()
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should free the path when a sender waiting for 100 Continue disconnects")]
+async fn f() -> Result<(), BoxError> {
+    use tokio::io::AsyncWriteExt;
+
+    let serve: Serve = serve().await;
+
+    let mut send_stream = tokio::net::TcpStream::connect(serve.addr).await?;
+    send_stream
+        .write_all(b"POST /mypath HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n")
+        .await?;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    // Disconnect while waiting
+    drop(send_stream);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("this is a content"))?;
+    let send_res = client.request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
 
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let (_, get_res_body) = client.request(get_req).await?.into_parts();
     assert_eq!(
-        get_header_value(&parts.headers, "content-type"),
-        Some("text/plain")
+        read_all_body(get_res_body).await,
+        "this is a content".as_bytes().to_vec()
     );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should keep a sender waiting for 100 Continue while it is connected")]
+async fn f() -> Result<(), BoxError> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let serve: Serve = serve().await;
+
+    let mut send_stream = tokio::net::TcpStream::connect(serve.addr).await?;
+    send_stream
+        .write_all(b"POST /mypath HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n")
+        .await?;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("this is a content"))?;
+    let send_res_future = tokio::spawn(client.request(send_req));
+
+    // The first sender is asked to continue to find whether it is connected
+    let mut buf = [0u8; 1024];
+    let n = send_stream.read(&mut buf).await?;
+    assert!(buf[..n].starts_with(b"HTTP/1.1 100 Continue"));
+    send_stream.write_all(b"hello").await?;
+
+    let send_res = send_res_future.await??;
+    assert_eq!(send_res.status(), http::StatusCode::BAD_REQUEST);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let (_, get_res_body) = client.request(get_req).await?.into_parts();
     assert_eq!(
-        get_header_value(&parts.headers, "content-length"),
-        Some(send_body_str.len().to_string().as_str())
+        read_all_body(get_res_body).await,
+        "hello".as_bytes().to_vec()
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should reject another sender while a sender waiting for 100 Continue is slow to send")]
+async fn f() -> Result<(), BoxError> {
+    use tokio::io::AsyncWriteExt;
+
+    let serve: Serve = serve().await;
+
+    let mut send_stream = tokio::net::TcpStream::connect(serve.addr).await?;
+    send_stream
+        .write_all(b"POST /mypath HTTP/1.1\r\nHost: localhost\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\n")
+        .await?;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // The first sender sends nothing even after 100 Continue
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("this is a content"))?;
+    let send_res =
+        tokio::time::timeout(std::time::Duration::from_secs(5), client.request(send_req)).await??;
+    assert_eq!(send_res.status(), http::StatusCode::BAD_REQUEST);
+
+    drop(send_stream);
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should report a waiting sender's headers to HEAD and ?peek without consuming it")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+
+    let head_req = hyper::Request::builder()
+        .method(hyper::Method::HEAD)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let head_res = client.request(head_req).await?;
+    assert_eq!(head_res.status(), http::StatusCode::NOT_FOUND);
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .header("Content-Type", "text/plain")
+        .header("Content-Disposition", "attachment; filename=\"myfile.txt\"")
+        .header("X-Piping", "mymetadata")
+        .body(hyper::Body::from("this is a content"))?;
+    let (send_res_parts, _send_res_body) = client.request(send_req).await?.into_parts();
+    assert_eq!(send_res_parts.status, http::StatusCode::OK);
+
+    let head_req = hyper::Request::builder()
+        .method(hyper::Method::HEAD)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let (head_res_parts, _) = client.request(head_req).await?.into_parts();
+    assert_eq!(head_res_parts.status, http::StatusCode::OK);
+    assert_eq!(
+        get_header_value(&head_res_parts.headers, "content-type"),
+        Some("text/plain")
     );
     assert_eq!(
-        get_header_value(&parts.headers, "content-disposition"),
-        None
+        get_header_value(&head_res_parts.headers, "content-length"),
+        Some("17")
     );
     assert_eq!(
-        get_header_value(&parts.headers, "access-control-allow-origin"),
-        Some("*")
+        get_header_value(&head_res_parts.headers, "content-disposition"),
+        Some("attachment; filename=\"myfile.txt\"")
     );
     assert_eq!(
-        get_header_value(&parts.headers, "x-robots-tag"),
-        Some("none")
+        get_header_value(&head_res_parts.headers, "x-piping"),
+        Some("mymetadata")
     );
+
+    let peek_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?peek", serve.addr).parse::<http::Uri>()?)
+        .body(hyper::Body::empty())?;
+    let (peek_res_parts, peek_res_body) = client.request(peek_req).await?.into_parts();
+    assert_eq!(peek_res_parts.status, http::StatusCode::OK);
+    let metadata: serde_json::Value = serde_json::from_slice(&read_all_body(peek_res_body).await)?;
+    assert_eq!(metadata["content_type"], "text/plain");
+    assert_eq!(metadata["content_length"], 17);
     assert_eq!(
-        get_header_value(&parts.headers, "access-control-expose-headers"),
-        Some("X-Piping"),
+        metadata["content_disposition"],
+        "attachment; filename=\"myfile.txt\""
     );
+    assert_eq!(metadata["x_piping"], serde_json::json!(["mymetadata"]));
+
+    // The sender is still waiting
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let (_, get_res_body) = client.request(get_req).await?.into_parts();
     assert_eq!(
-        get_header_value(&parts.headers, "X-Piping"),
-        Some("mymetadata"),
+        read_all_body(get_res_body).await,
+        "this is a content".as_bytes().to_vec()
     );
 
     serve.shutdown_tx.send(()).expect("shutdown failed");
     Ok(())
 }
 
-#[it("should pass multiple X-Piping")]
+#[it("should cancel a waiting sender by DELETE with its cancel token")]
 async fn f() -> Result<(), BoxError> {
     let serve: Serve = serve().await;
 
     let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
 
-    let send_body_str = "this is a content";
     let send_req = hyper::Request::builder()
         .method(hyper::Method::POST)
-        .header("Content-Type", "text/plain")
-        .header("X-Piping", "mymetadata1")
-        .header("X-Piping", "mymetadata2")
-        .header("X-Piping", "mymetadata3")
         .uri(uri.clone())
-        .body(hyper::Body::from(send_body_str))?;
+        .body(hyper::Body::from("this is a content"))?;
+    let (send_res_parts, send_res_body) = client.request(send_req).await?.into_parts();
+    let cancel_token = get_header_value(&send_res_parts.headers, "x-piping-cancel-token")
+        .unwrap()
+        .to_owned();
+
+    // Wrong token
+    let delete_req = hyper::Request::builder()
+        .method(hyper::Method::DELETE)
+        .uri(uri.clone())
+        .header("X-Piping-Cancel-Token", "wrongtoken")
+        .body(hyper::Body::empty())?;
+    let delete_res = client.request(delete_req).await?;
+    assert_eq!(delete_res.status(), http::StatusCode::NOT_FOUND);
+
+    let delete_req = hyper::Request::builder()
+        .method(hyper::Method::DELETE)
+        .uri(uri.clone())
+        .header("X-Piping-Cancel-Token", cancel_token)
+        .body(hyper::Body::empty())?;
+    let delete_res = client.request(delete_req).await?;
+    assert_eq!(delete_res.status(), http::StatusCode::OK);
+
+    let send_res_body = String::from_utf8(read_all_body(send_res_body).await)?;
+    assert!(send_res_body.ends_with("[ERROR] Cancelled by DELETE on '/mypath'.\n"));
 
+    // The path is free
+    let head_req = hyper::Request::builder()
+        .method(hyper::Method::HEAD)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let head_res = client.request(head_req).await?;
+    assert_eq!(head_res.status(), http::StatusCode::NOT_FOUND);
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should cancel a waiting receiver by DELETE with the cancel token it specified")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
     let client = Client::new();
-    let send_res = client.request(send_req).await?;
-    let (send_res_parts, _send_res_body) = send_res.into_parts();
-    assert_eq!(send_res_parts.status, http::StatusCode::OK);
 
     let get_req = hyper::Request::builder()
         .method(hyper::Method::GET)
         .uri(uri.clone())
+        .header("X-Piping-Cancel-Token", "mytoken")
         .body(hyper::Body::empty())?;
-    let client = Client::new();
-    let (parts, body) = client.request(get_req).await?.into_parts();
+    let get_res_future = tokio::spawn(client.request(get_req));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
 
-    let all_bytes: Vec<u8> = read_all_body(body).await;
-
-    let expect = send_body_str.to_owned().into_bytes();
-    assert_eq!(all_bytes, expect);
+    let delete_req = hyper::Request::builder()
+        .method(hyper::Method::DELETE)
+        .uri(uri.clone())
+        .header("X-Piping-Cancel-Token", "mytoken")
+        .body(hyper::Body::empty())?;
+    let delete_res = client.request(delete_req).await?;
+    assert_eq!(delete_res.status(), http::StatusCode::OK);
 
+    let (get_res_parts, get_res_body) = get_res_future.await??.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::GONE);
     assert_eq!(
-        get_header_value(&parts.headers, "access-control-expose-headers"),
-        Some("X-Piping"),
+        read_all_body(get_res_body).await,
+        "[ERROR] Cancelled by DELETE on '/mypath'.\n"
+            .as_bytes()
+            .to_vec()
     );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should queue senders with ?queue and pair them with receivers in order")]
+async fn f() -> Result<(), BoxError> {
+    use futures::StreamExt;
+
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let queue_uri = format!("http://{}/mypath?queue", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+
+    let send_req1 = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("content1"))?;
+    let (_, send_res_body1) = client.request(send_req1).await?.into_parts();
+
+    let send_req2 = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(queue_uri.clone())
+        .body(hyper::Body::from("content2"))?;
+    let (send_res_parts2, mut send_res_body2) = client.request(send_req2).await?.into_parts();
+    assert_eq!(send_res_parts2.status, http::StatusCode::OK);
     assert_eq!(
-        parts
-            .headers
-            .get_all("X-Piping")
-            .into_iter()
-            .collect::<Vec<_>>(),
-        vec!["mymetadata1", "mymetadata2", "mymetadata3"],
+        send_res_body2.next().await.unwrap()?,
+        "[INFO] Queued behind 1 sender(s)...\n"
     );
 
+    // Without ?queue
+    let send_req3 = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("content3"))?;
+    let send_res3 = client.request(send_req3).await?;
+    assert_eq!(send_res3.status(), http::StatusCode::BAD_REQUEST);
+
+    for expected in ["content1", "content2"] {
+        let get_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(uri.clone())
+            .body(hyper::Body::empty())?;
+        let (_, get_res_body) = client.request(get_req).await?.into_parts();
+        assert_eq!(
+            read_all_body(get_res_body).await,
+            expected.as_bytes().to_vec()
+        );
+    }
+
+    assert!(String::from_utf8(read_all_body(send_res_body1).await)?
+        .ends_with("[INFO] Sent successfully!\n"));
+    let send_res_body2 = String::from_utf8(read_all_body(send_res_body2).await)?;
+    assert!(send_res_body2.starts_with("[INFO] Waiting for 1 receiver(s)...\n"));
+    assert!(send_res_body2.ends_with("[INFO] Sent successfully!\n"));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should queue receivers with ?queue and pair them with senders in order")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let queue_uri = format!("http://{}/mypath?queue", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+
+    let mut get_res_futures = Vec::new();
+    for _ in 0..3 {
+        let get_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(queue_uri.clone())
+            .body(hyper::Body::empty())?;
+        get_res_futures.push(tokio::spawn(client.request(get_req)));
+        // Keep the order of the receivers
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+
+    // Without ?queue
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res = client.request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::BAD_REQUEST);
+
+    for (i, get_res_future) in get_res_futures.into_iter().enumerate() {
+        let content = format!("content{}", i);
+        let send_req = hyper::Request::builder()
+            .method(hyper::Method::POST)
+            .uri(uri.clone())
+            .body(hyper::Body::from(content.clone()))?;
+        let (_, send_res_body) = client.request(send_req).await?.into_parts();
+        let (_, get_res_body) = get_res_future.await??.into_parts();
+        assert_eq!(
+            read_all_body(get_res_body).await,
+            content.as_bytes().to_vec()
+        );
+        read_all_body(send_res_body).await;
+    }
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should broadcast with ?broadcast to receivers joining in the middle")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+
+    // Receiver waiting before the broadcast starts
+    let get_req1 = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res_future1 = tokio::spawn(client.request(get_req1));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let (mut send_body_sender, send_body) = hyper::Body::channel();
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?broadcast", serve.addr).parse::<http::Uri>()?)
+        .header("Content-Type", "text/plain")
+        .body(send_body)?;
+    let (send_res_parts, send_res_body) = client.request(send_req).await?.into_parts();
+    assert_eq!(send_res_parts.status, http::StatusCode::OK);
+
+    send_body_sender.send_data("hello, ".into()).await?;
+    let (get_res_parts1, get_res_body1) = get_res_future1.await??.into_parts();
+    assert_eq!(
+        get_header_value(&get_res_parts1.headers, "content-type"),
+        Some("text/plain")
+    );
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    // Receiver joining in the middle
+    let get_req2 = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let (get_res_parts2, get_res_body2) = client.request(get_req2).await?.into_parts();
+    assert_eq!(get_res_parts2.status, http::StatusCode::OK);
+    assert_eq!(
+        get_header_value(&get_res_parts2.headers, "content-length"),
+        None
+    );
+
+    // Another sender is rejected during the broadcast
+    let send_req2 = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("this is a content"))?;
+    let send_res2 = client.request(send_req2).await?;
+    assert_eq!(send_res2.status(), http::StatusCode::BAD_REQUEST);
+
+    send_body_sender.send_data("world".into()).await?;
+    drop(send_body_sender);
+
+    assert_eq!(
+        read_all_body(get_res_body1).await,
+        "hello, world".as_bytes().to_vec()
+    );
+    assert_eq!(
+        read_all_body(get_res_body2).await,
+        "world".as_bytes().to_vec()
+    );
+    let send_res_body = String::from_utf8(read_all_body(send_res_body).await)?;
+    assert!(send_res_body.starts_with("[INFO] Start sending to 1 receiver(s)...\n"));
+    assert!(send_res_body.ends_with("[INFO] Sent successfully!\n"));
+
+    // A multipart broadcast without boundary should be rejected
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?broadcast", serve.addr).parse::<http::Uri>()?)
+        .header("Content-Type", "multipart/form-data")
+        .body(hyper::Body::from("this is a content"))?;
+    let send_res = client.request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::BAD_REQUEST);
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should merge senders into one receiver with ?merge")]
+async fn f() -> Result<(), BoxError> {
+    use futures::StreamExt;
+
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+
+    // Sender waiting before the receiver comes
+    let send_req1 = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("hello, "))?;
+    let (_, send_res_body1) = client.request(send_req1).await?.into_parts();
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?merge", serve.addr).parse::<http::Uri>()?)
+        .body(hyper::Body::empty())?;
+    let (get_res_parts, mut get_res_body) = client.request(get_req).await?.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::OK);
+    assert!(String::from_utf8(read_all_body(send_res_body1).await)?
+        .ends_with("[INFO] Sent successfully!\n"));
+
+    let send_req2 = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("world"))?;
+    let (_, send_res_body2) = client.request(send_req2).await?.into_parts();
+    assert!(String::from_utf8(read_all_body(send_res_body2).await)?
+        .ends_with("[INFO] Sent successfully!\n"));
+
+    // Another receiver is rejected during the merge
+    let get_req2 = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res2 = client.request(get_req2).await?;
+    assert_eq!(get_res2.status(), http::StatusCode::BAD_REQUEST);
+
+    let mut merged: Vec<u8> = Vec::new();
+    while merged.len() < "hello, world".len() {
+        merged.extend_from_slice(&get_res_body.next().await.unwrap()?);
+    }
+    assert_eq!(merged, "hello, world".as_bytes().to_vec());
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should merge senders streaming at the same time with ?merge and ?merge=length")]
+async fn f() -> Result<(), BoxError> {
+    use futures::StreamExt;
+    use std::collections::HashMap;
+    use std::convert::TryInto;
+
+    // Bodies of senders in frames prefixed with sender's ID and length (None: incomplete)
+    fn parse_length_frames(mut frames: &[u8]) -> Option<HashMap<u64, Vec<u8>>> {
+        let mut sender_id_to_body: HashMap<u64, Vec<u8>> = HashMap::new();
+        while !frames.is_empty() {
+            if frames.len() < 12 {
+                return None;
+            }
+            let sender_id = u64::from_be_bytes(frames[..8].try_into().unwrap());
+            let len = u32::from_be_bytes(frames[8..12].try_into().unwrap()) as usize;
+            if frames.len() < 12 + len {
+                return None;
+            }
+            sender_id_to_body
+                .entry(sender_id)
+                .or_default()
+                .extend_from_slice(&frames[12..12 + len]);
+            frames = &frames[12 + len..];
+        }
+        Some(sender_id_to_body)
+    }
+
+    let serve: Serve = serve().await;
+
+    let client = Client::new();
+
+    // Sender sending its chunks at intervals
+    fn slow_body(chunks: Vec<&'static str>) -> hyper::Body {
+        hyper::Body::wrap_stream(futures::stream::iter(chunks).then(|chunk| async move {
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            Ok::<_, BoxError>(chunk)
+        }))
+    }
+
+    for framing in ["raw", "length"] {
+        let get_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(format!("http://{}/mypath?merge={}", serve.addr, framing).parse::<http::Uri>()?)
+            .body(hyper::Body::empty())?;
+        let (get_res_parts, get_res_body) = client.request(get_req).await?.into_parts();
+        assert_eq!(get_res_parts.status, http::StatusCode::OK);
+
+        let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+        let send_req1 = hyper::Request::builder()
+            .method(hyper::Method::POST)
+            .uri(uri.clone())
+            .body(slow_body(vec!["a1", "a2", "a3"]))?;
+        let send_res_future1 = tokio::spawn(client.request(send_req1));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        let send_req2 = hyper::Request::builder()
+            .method(hyper::Method::POST)
+            .uri(uri.clone())
+            .body(slow_body(vec!["b1", "b2", "b3"]))?;
+        let send_res_future2 = tokio::spawn(client.request(send_req2));
+        for send_res_future in [send_res_future1, send_res_future2] {
+            let send_res_body = send_res_future.await??.into_body();
+            assert!(String::from_utf8(read_all_body(send_res_body).await)?
+                .ends_with("[INFO] Sent successfully!\n"));
+        }
+
+        let mut get_res_body = get_res_body;
+        let mut merged: Vec<u8> = Vec::new();
+        if framing == "raw" {
+            while merged.len() < "a1a2a3b1b2b3".len() {
+                merged.extend_from_slice(&get_res_body.next().await.unwrap()?);
+            }
+            // One sender after another
+            assert_eq!(merged, "a1a2a3b1b2b3".as_bytes().to_vec());
+        } else {
+            let sender_id_to_body = loop {
+                merged.extend_from_slice(&get_res_body.next().await.unwrap()?);
+                match parse_length_frames(&merged) {
+                    Some(sender_id_to_body)
+                        if sender_id_to_body.values().map(Vec::len).sum::<usize>() == 12 =>
+                    {
+                        break sender_id_to_body
+                    }
+                    _ => continue,
+                }
+            };
+            assert_eq!(sender_id_to_body.len(), 2);
+            let mut bodies: Vec<Vec<u8>> = sender_id_to_body.into_values().collect();
+            bodies.sort();
+            assert_eq!(
+                bodies,
+                vec!["a1a2a3".as_bytes().to_vec(), "b1b2b3".as_bytes().to_vec()]
+            );
+        }
+        // The receiver leaves to free the path
+        drop(get_res_body);
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should merge senders in NDJSON envelopes with ?merge=ndjson")]
+async fn f() -> Result<(), BoxError> {
+    use futures::StreamExt;
+
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?merge=ndjson", serve.addr).parse::<http::Uri>()?)
+        .body(hyper::Body::empty())?;
+    let get_res_future = tokio::spawn(client.request(get_req));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .header("Content-Type", "text/plain")
+        .header("X-Piping", "mymetadata")
+        .body(hyper::Body::from("hello"))?;
+    let (_, send_res_body) = client.request(send_req).await?.into_parts();
+    read_all_body(send_res_body).await;
+
+    let (get_res_parts, mut get_res_body) = get_res_future.await??.into_parts();
+    assert_eq!(
+        get_header_value(&get_res_parts.headers, "content-type"),
+        Some("application/x-ndjson")
+    );
+    let mut merged = String::new();
+    while merged.lines().count() < 3 {
+        merged.push_str(std::str::from_utf8(&get_res_body.next().await.unwrap()?)?);
+    }
+    let envelopes = merged
+        .lines()
+        .map(serde_json::from_str::<serde_json::Value>)
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        envelopes,
+        vec![
+            serde_json::json!({"event": "start", "sender_id": 1, "content_type": "text/plain", "x_piping": ["mymetadata"]}),
+            serde_json::json!({"event": "data", "sender_id": 1, "data": "aGVsbG8="}),
+            serde_json::json!({"event": "end", "sender_id": 1}),
+        ]
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should relay a request to a worker and its reply to the client with ?rpc")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let client = Client::new();
+
+    let worker_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?rpc=worker", serve.addr).parse::<http::Uri>()?)
+        .body(hyper::Body::empty())?;
+    let worker_res_future = tokio::spawn(client.request(worker_req));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let client_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?rpc", serve.addr).parse::<http::Uri>()?)
+        .header("X-My-Header", "myvalue")
+        .body(hyper::Body::wrap_stream(futures::stream::iter(vec![Ok::<
+            _,
+            BoxError,
+        >(
+            "ping",
+        )])))?;
+    let client_res_future = tokio::spawn(client.request(client_req));
+
+    let (worker_res_parts, worker_res_body) = worker_res_future.await??.into_parts();
+    assert_eq!(
+        get_header_value(&worker_res_parts.headers, "content-type"),
+        Some("message/http")
+    );
+    let rpc_id = get_header_value(&worker_res_parts.headers, "x-piping-rpc-id")
+        .unwrap()
+        .to_owned();
+    let serialized_req = String::from_utf8(read_all_body(worker_res_body).await)?;
+    assert!(serialized_req.starts_with("POST /mypath?rpc HTTP/1.1\r\n"));
+    assert!(serialized_req.contains("\r\nx-my-header: myvalue\r\n"));
+    // The body is not chunked in the serialized request
+    assert!(!serialized_req.contains("transfer-encoding"));
+    assert!(serialized_req.ends_with("\r\n\r\nping"));
+
+    let reply_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?rpc=reply", serve.addr).parse::<http::Uri>()?)
+        .header("X-Piping-Rpc-Id", rpc_id)
+        .body(hyper::Body::from(
+            "HTTP/1.1 201 Created\r\nContent-Type: text/plain\r\nContent-Length: 4\r\n\r\npong",
+        ))?;
+    let reply_res = client.request(reply_req).await?;
+    assert_eq!(reply_res.status(), http::StatusCode::OK);
+
+    let (client_res_parts, client_res_body) = client_res_future.await??.into_parts();
+    assert_eq!(client_res_parts.status, http::StatusCode::CREATED);
+    assert_eq!(
+        get_header_value(&client_res_parts.headers, "content-type"),
+        Some("text/plain")
+    );
+    assert_eq!(
+        read_all_body(client_res_body).await,
+        "pong".as_bytes().to_vec()
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should respond 502 to the RPC client when the worker leaves before reading the request")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let client = Client::new();
+
+    let worker_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?rpc=worker", serve.addr).parse::<http::Uri>()?)
+        .body(hyper::Body::empty())?;
+    let worker_res_future = tokio::spawn(client.request(worker_req));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let (mut client_body_sender, client_body) = hyper::Body::channel();
+    let client_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?rpc", serve.addr).parse::<http::Uri>()?)
+        .body(client_body)?;
+    let client_res_future = tokio::spawn(client.request(client_req));
+    client_body_sender.send_data("ping".into()).await?;
+
+    // The worker leaves while the client is still sending
+    let worker_res = worker_res_future.await??;
+    drop(worker_res);
+
+    let client_res = client_res_future.await??;
+    assert_eq!(client_res.status(), http::StatusCode::BAD_GATEWAY);
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should decode a chunked reply of the worker with ?rpc")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let client = Client::new();
+
+    let worker_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?rpc=worker", serve.addr).parse::<http::Uri>()?)
+        .body(hyper::Body::empty())?;
+    let worker_res_future = tokio::spawn(client.request(worker_req));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let client_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?rpc", serve.addr).parse::<http::Uri>()?)
+        .body(hyper::Body::empty())?;
+    let client_res_future = tokio::spawn(client.request(client_req));
+
+    let worker_res_parts = worker_res_future.await??.into_parts().0;
+    let rpc_id = get_header_value(&worker_res_parts.headers, "x-piping-rpc-id")
+        .unwrap()
+        .to_owned();
+
+    // Chunk-size lines are split across the pieces of the reply
+    let reply_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?rpc=reply", serve.addr).parse::<http::Uri>()?)
+        .header("X-Piping-Rpc-Id", rpc_id)
+        .body(hyper::Body::wrap_stream(futures::stream::iter(vec![
+            Ok::<_, BoxError>("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r"),
+            Ok("\npi"),
+            Ok("ng\r\n6;ext=1\r\n, pong\r\n0"),
+            Ok("\r\nX-My-Trailer: a\r\n\r\n"),
+        ])))?;
+    let reply_res = client.request(reply_req).await?;
+    assert_eq!(reply_res.status(), http::StatusCode::OK);
+
+    let (client_res_parts, client_res_body) = client_res_future.await??.into_parts();
+    assert_eq!(client_res_parts.status, http::StatusCode::OK);
+    assert_eq!(
+        read_all_body(client_res_body).await,
+        "ping, pong".as_bytes().to_vec()
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should splice two peers CONNECTing to the same path")]
+async fn f() -> Result<(), BoxError> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let serve: Serve = serve().await;
+
+    async fn connect(addr: SocketAddr) -> Result<tokio::net::TcpStream, BoxError> {
+        let mut stream = tokio::net::TcpStream::connect(addr).await?;
+        stream
+            .write_all(b"CONNECT /mytunnel HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await?;
+        Ok(stream)
+    }
+    async fn read_head(stream: &mut tokio::net::TcpStream) -> Result<String, BoxError> {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            let mut byte = [0u8; 1];
+            stream.read_exact(&mut byte).await?;
+            head.push(byte[0]);
+        }
+        Ok(String::from_utf8(head)?)
+    }
+
+    let mut stream1 = connect(serve.addr).await?;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let mut stream2 = connect(serve.addr).await?;
+
+    assert!(read_head(&mut stream1)
+        .await?
+        .starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(read_head(&mut stream2)
+        .await?
+        .starts_with("HTTP/1.1 200 OK\r\n"));
+
+    stream1.write_all(b"hello").await?;
+    let mut buf = [0u8; 5];
+    stream2.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"hello");
+
+    stream2.write_all(b"world").await?;
+    stream1.read_exact(&mut buf).await?;
+    assert_eq!(&buf, b"world");
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should store a body with ?mailbox and deliver it to a later receiver")]
+async fn f() -> Result<(), BoxError> {
+    let mailbox_dir =
+        std::env::temp_dir().join(format!("piping-server-test-mailbox-{}", std::process::id()));
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        mailbox_dir: Some(mailbox_dir.clone()),
+        receiver_wait_timeout: Some(std::time::Duration::from_millis(200)),
+        ..PipingServerConfig::default()
+    })
+    .await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let mailbox_uri = format!("http://{}/mypath?mailbox", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(mailbox_uri.clone())
+        .header("Content-Type", "text/plain")
+        .body(hyper::Body::from("this is a content"))?;
+    let (send_res_parts, send_res_body) = client.request(send_req).await?.into_parts();
+    assert_eq!(send_res_parts.status, http::StatusCode::OK);
+    assert!(String::from_utf8(read_all_body(send_res_body).await)?.starts_with("[INFO] Stored"));
+
+    // Another mail on the same path should be rejected
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(mailbox_uri.clone())
+        .body(hyper::Body::from("another content"))?;
+    let send_res = client.request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::BAD_REQUEST);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let (get_res_parts, get_res_body) = client.request(get_req).await?.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::OK);
+    assert_eq!(
+        get_header_value(&get_res_parts.headers, "content-type"),
+        Some("text/plain")
+    );
+    assert_eq!(
+        get_header_value(&get_res_parts.headers, "content-length"),
+        Some("17")
+    );
+    assert_eq!(
+        read_all_body(get_res_body).await,
+        "this is a content".as_bytes().to_vec()
+    );
+
+    // The mail should be delivered only once
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res = client.request(get_req).await?;
+    assert_eq!(get_res.status(), http::StatusCode::REQUEST_TIMEOUT);
+
+    // A multipart mail without boundary should be rejected
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(mailbox_uri.clone())
+        .header("Content-Type", "multipart/form-data")
+        .body(hyper::Body::from("this is a content"))?;
+    let send_res = client.request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::BAD_REQUEST);
+    assert_eq!(std::fs::read_dir(&mailbox_dir)?.count(), 0);
+
+    std::fs::remove_dir(&mailbox_dir)?;
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should deliver a body with ?mailbox to a receiver waiting before it is stored")]
+async fn f() -> Result<(), BoxError> {
+    let mailbox_dir = std::env::temp_dir().join(format!(
+        "piping-server-test-mailbox-waiting-{}",
+        std::process::id()
+    ));
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        mailbox_dir: Some(mailbox_dir.clone()),
+        ..PipingServerConfig::default()
+    })
+    .await;
+
+    let client = Client::new();
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?)
+        .body(hyper::Body::empty())?;
+    let get_res_future = tokio::spawn(client.request(get_req));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?mailbox", serve.addr).parse::<http::Uri>()?)
+        .body(hyper::Body::from("this is a content"))?;
+    let (send_res_parts, send_res_body) = client.request(send_req).await?.into_parts();
+    assert_eq!(send_res_parts.status, http::StatusCode::OK);
+    assert!(String::from_utf8(read_all_body(send_res_body).await)?
+        .contains("delivered to the waiting receiver"));
+
+    let (get_res_parts, get_res_body) =
+        tokio::time::timeout(std::time::Duration::from_secs(5), get_res_future)
+            .await???
+            .into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::OK);
+    assert_eq!(
+        read_all_body(get_res_body).await,
+        "this is a content".as_bytes().to_vec()
+    );
+    // The spooled file is removed after the delivery
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert_eq!(std::fs::read_dir(&mailbox_dir)?.count(), 0);
+
+    std::fs::remove_dir(&mailbox_dir)?;
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should reject ?mailbox when --mailbox-dir is not given")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath?mailbox", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("this is a content"))?;
+    let send_res = client.request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::BAD_REQUEST);
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should read sender's body ahead of the receiver up to --max-read-ahead-memory")]
+async fn f() -> Result<(), BoxError> {
+    let max_read_ahead_memory: u64 = 1024 * 1024;
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        read_ahead_buffer_size: 4 * 1024 * 1024,
+        max_read_ahead_memory,
+        ..PipingServerConfig::default()
+    })
+    .await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let stats_uri = format!("http://{}/stats", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res_join_handle = tokio::spawn(client.request(get_req));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let content = vec![b'a'; 8 * 1024 * 1024];
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from(content.clone()))?;
+    let _send_res_join_handle = tokio::spawn(client.request(send_req));
+    let (get_res_parts, get_res_body) = get_res_join_handle.await??.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::OK);
+
+    // Sender's body should be read ahead while the receiver is not reading
+    let get_read_ahead_memory = || async {
+        let stats_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(stats_uri.clone())
+            .body(hyper::Body::empty())?;
+        let stats_res = client.request(stats_req).await?;
+        let stats: serde_json::Value =
+            serde_json::from_slice(&read_all_body(stats_res.into_body()).await)?;
+        assert_eq!(stats["max_read_ahead_memory"], max_read_ahead_memory);
+        Ok::<_, BoxError>(stats["read_ahead_memory"].as_u64().unwrap())
+    };
+    let mut n_read_ahead_bytes = 0;
+    for _ in 0..50 {
+        n_read_ahead_bytes = get_read_ahead_memory().await?;
+        if n_read_ahead_bytes > 0 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    assert!(n_read_ahead_bytes > 0);
+    assert!(n_read_ahead_bytes <= max_read_ahead_memory);
+
+    assert_eq!(read_all_body(get_res_body).await, content);
+    // The memory should be released after the transfer
+    assert_eq!(get_read_ahead_memory().await?, 0);
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should archive all files of a multipart upload into tar for a receiver accepting it")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .header("Accept", "application/x-tar")
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res_join_handle = tokio::spawn(client.request(get_req));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let send_body = "--BOUNDARY\r\nContent-Disposition: form-data; name=\"input_file\"; filename=\"a.txt\"\r\nContent-Length: 5\r\n\r\nhello\r\n--BOUNDARY\r\nContent-Disposition: form-data; name=\"input_text\"\r\n\r\nnot a file\r\n--BOUNDARY\r\nContent-Disposition: form-data; name=\"input_file\"; filename=\"../b.txt\"\r\nContent-Length: 6\r\n\r\nworld!\r\n--BOUNDARY--\r\n";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "multipart/form-data; boundary=BOUNDARY")
+        .uri(uri.clone())
+        .body(hyper::Body::from(send_body))?;
+    let _send_res = client.request(send_req).await?;
+
+    let (get_res_parts, get_res_body) = get_res_join_handle.await??.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::OK);
+    assert_eq!(
+        get_header_value(&get_res_parts.headers, "content-type"),
+        Some("application/x-tar")
+    );
+    let tar = read_all_body(get_res_body).await;
+    // Two headers with padded contents and the end of the archive
+    assert_eq!(tar.len(), 512 * 6);
+    assert_eq!(&tar[0..6], b"a.txt\0");
+    assert_eq!(&tar[124..136], b"00000000005\0");
+    assert_eq!(&tar[257..263], b"ustar\0");
+    assert_eq!(&tar[512..517], b"hello");
+    assert_eq!(&tar[1024..1030], b"b.txt\0");
+    assert_eq!(&tar[1024 + 124..1024 + 136], b"00000000006\0");
+    assert_eq!(&tar[1536..1542], b"world!");
+    assert!(tar[2048..].iter().all(|byte| *byte == 0));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should reject tar of a multipart file without Content-Length")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath?archive=tar", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res_join_handle = tokio::spawn(client.request(get_req));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let send_body = "--BOUNDARY\r\nContent-Disposition: form-data; name=\"input_file\"; filename=\"a.txt\"\r\n\r\nhello\r\n--BOUNDARY--\r\n";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "multipart/form-data; boundary=BOUNDARY")
+        .uri(uri.clone())
+        .body(hyper::Body::from(send_body))?;
+    let send_res_body = client.request(send_req).await?.into_body();
+
+    let (get_res_parts, get_res_body) = get_res_join_handle.await??.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::BAD_REQUEST);
+    assert!(String::from_utf8(read_all_body(get_res_body).await)?
+        .contains("'a.txt' has no Content-Length for tar"));
+    let send_res_body = String::from_utf8(read_all_body(send_res_body).await)?;
+    assert!(send_res_body.ends_with("use ?archive=zip instead\n"));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should archive all files of a multipart upload into zip by ?archive")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let send_uri = format!("http://{}/mypath?archive", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res_join_handle = tokio::spawn(client.request(get_req));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let send_body = "--BOUNDARY\r\nContent-Disposition: form-data; name=\"input_file\"; filename=\"a.txt\"\r\n\r\nhello\r\n--BOUNDARY\r\nContent-Disposition: form-data; name=\"input_file\"; filename=\"b.txt\"\r\n\r\nworld!\r\n--BOUNDARY--\r\n";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "multipart/form-data; boundary=BOUNDARY")
+        .uri(send_uri.clone())
+        .body(hyper::Body::from(send_body))?;
+    let _send_res = client.request(send_req).await?;
+
+    let (get_res_parts, get_res_body) = get_res_join_handle.await??.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::OK);
+    assert_eq!(
+        get_header_value(&get_res_parts.headers, "content-type"),
+        Some("application/zip")
+    );
+    let zip = read_all_body(get_res_body).await;
+    assert_eq!(&zip[0..4], b"PK\x03\x04");
+    // Local header, content and data descriptor with CRC-32 of "hello"
+    assert_eq!(&zip[30..35], b"a.txt");
+    assert_eq!(&zip[35..40], b"hello");
+    assert_eq!(&zip[40..44], b"PK\x07\x08");
+    assert_eq!(&zip[44..48], &0x3610a686u32.to_le_bytes());
+    assert_eq!(&zip[48..52], &5u32.to_le_bytes());
+    // End of central directory with 2 entries
+    let eocd = &zip[zip.len() - 22..];
+    assert_eq!(&eocd[0..4], b"PK\x05\x06");
+    assert_eq!(&eocd[10..12], &2u16.to_le_bytes());
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should abort a multipart upload of files without ?archive instead of dropping them")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res_join_handle = tokio::spawn(client.request(get_req));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let send_body = "--BOUNDARY\r\nContent-Disposition: form-data; name=\"input_file\"; filename=\"a.txt\"\r\n\r\nhello\r\n--BOUNDARY\r\nContent-Disposition: form-data; name=\"input_file\"; filename=\"b.txt\"\r\n\r\nworld!\r\n--BOUNDARY--\r\n";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "multipart/form-data; boundary=BOUNDARY")
+        .uri(uri.clone())
+        .body(hyper::Body::from(send_body))?;
+    let send_res_body = client.request(send_req).await?.into_body();
+
+    // The receiver's response is aborted, possibly before its head
+    if let Ok(get_res) = get_res_join_handle.await? {
+        assert!(hyper::body::to_bytes(get_res.into_body()).await.is_err());
+    }
+    let send_res_body = String::from_utf8(read_all_body(send_res_body).await)?;
+    assert!(send_res_body.contains("'b.txt' can not be sent without ?archive"));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should rewrite Content-Disposition of a multipart file into attachment")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    for (filename, expected_content_disposition) in [
+        ("a.png", "attachment; filename=\"a.png\""),
+        (
+            "画像 1.png",
+            "attachment; filename=\"__ 1.png\"; filename*=UTF-8''%E7%94%BB%E5%83%8F%201.png",
+        ),
+    ] {
+        let get_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .uri(uri.clone())
+            .body(hyper::Body::empty())?;
+        let get_res_join_handle = tokio::spawn(client.request(get_req));
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+        let send_body = format!("--BOUNDARY\r\nContent-Disposition: form-data; name=\"input_file\"; filename=\"{}\"\r\nContent-Type: image/png\r\n\r\nhello\r\n--BOUNDARY--\r\n", filename);
+        let send_req = hyper::Request::builder()
+            .method(hyper::Method::POST)
+            .header("Content-Type", "multipart/form-data; boundary=BOUNDARY")
+            .uri(uri.clone())
+            .body(hyper::Body::from(send_body))?;
+        let _send_res = client.request(send_req).await?;
+
+        let (get_res_parts, get_res_body) = get_res_join_handle.await??.into_parts();
+        assert_eq!(
+            get_header_value(&get_res_parts.headers, "content-type"),
+            Some("image/png")
+        );
+        assert_eq!(
+            get_header_value(&get_res_parts.headers, "content-disposition"),
+            Some(expected_content_disposition)
+        );
+        assert_eq!(
+            read_all_body(get_res_body).await,
+            "hello".as_bytes().to_vec()
+        );
+    }
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should send a multipart text field as UTF-8 text without Content-Disposition")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res_join_handle = tokio::spawn(client.request(get_req));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let send_body = "--BOUNDARY\r\nContent-Disposition: form-data; name=\"input_text\"\r\n\r\nこんにちは\r\n--BOUNDARY--\r\n";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "multipart/form-data; boundary=BOUNDARY")
+        .uri(uri.clone())
+        .body(hyper::Body::from(send_body))?;
+    let _send_res = client.request(send_req).await?;
+
+    let (get_res_parts, get_res_body) = get_res_join_handle.await??.into_parts();
+    assert_eq!(
+        get_header_value(&get_res_parts.headers, "content-type"),
+        Some("text/plain; charset=utf-8")
+    );
+    assert_eq!(
+        get_header_value(&get_res_parts.headers, "content-disposition"),
+        None
+    );
+    assert_eq!(
+        read_all_body(get_res_body).await,
+        "こんにちは".as_bytes().to_vec()
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should not pair a duplex peer with one which has left")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath?duplex", serve.addr).parse::<http::Uri>()?;
+    let stale_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("stale"))?;
+    let stale_join_handle = tokio::spawn(Client::new().request(stale_req));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    // The first peer leaves while waiting
+    stale_join_handle.abort();
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let client = Client::new();
+    let req1 = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("from peer1"))?;
+    let res1_join_handle = tokio::spawn(client.request(req1));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    let req2 = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("from peer2"))?;
+    let res2 = client.request(req2).await?;
+    let res1 = res1_join_handle.await??;
+    assert_eq!(
+        read_all_body(res1.into_body()).await,
+        "from peer2".as_bytes().to_vec()
+    );
+    assert_eq!(
+        read_all_body(res2.into_body()).await,
+        "from peer1".as_bytes().to_vec()
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should respond 408 to a receiver waiting longer than --receiver-wait-timeout")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        receiver_wait_timeout: Some(std::time::Duration::from_millis(200)),
+        ..PipingServerConfig::default()
+    })
+    .await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let (get_res_parts, _) = client.request(get_req).await?.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::REQUEST_TIMEOUT);
+
+    // The path should be available again
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res_future = client.request(get_req);
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("this is a content"))?;
+    let _send_res = client.request(send_req).await?;
+    let (get_res_parts, get_res_body) = get_res_future.await?.into_parts();
+    assert_eq!(get_res_parts.status, http::StatusCode::OK);
+    assert_eq!(
+        read_all_body(get_res_body).await,
+        "this is a content".as_bytes().to_vec()
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should send an error to a sender waiting longer than --sender-wait-timeout")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        sender_wait_timeout: Some(std::time::Duration::from_millis(200)),
+        ..PipingServerConfig::default()
+    })
+    .await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let client = Client::new();
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("this is a content"))?;
+    let (_, send_res_body) = client.request(send_req).await?.into_parts();
+    let message = String::from_utf8(read_all_body(send_res_body).await)?;
+    assert!(message.ends_with("[ERROR] Timed out waiting for receivers on '/mypath'.\n"));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should tell the sender when the receiver disconnects in the middle of transfer")]
+async fn f() -> Result<(), BoxError> {
+    use futures::stream::StreamExt;
+
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+
+    let (mut send_body_sender, send_body) = hyper::Body::channel();
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(send_body)?;
+    let client = Client::new();
+    let send_res = client.request(send_req).await?;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let (_, mut get_res_body) = Client::new().request(get_req).await?.into_parts();
+    send_body_sender.send_data("hello".into()).await?;
+    assert_eq!(get_res_body.next().await.unwrap()?, "hello");
+    // Receiver aborts
+    drop(get_res_body);
+
+    let send_res_string = String::from_utf8(read_all_body(send_res.into_body()).await)?;
+    assert!(send_res_string.ends_with("[ERROR] receiver disconnected after 5 bytes\n"));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should abort the receiver's response when the sender aborts")]
+async fn f() -> Result<(), BoxError> {
+    use futures::stream::StreamExt;
+
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+
+    let (abort_tx, abort_rx) = oneshot::channel::<()>();
+    let send_body = futures::stream::iter(vec![Ok::<_, BoxError>("hello")]).chain(
+        futures::stream::once(async {
+            let _ = abort_rx.await;
+            Err::<&str, BoxError>("aborted".into())
+        }),
+    );
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::wrap_stream(send_body))?;
+    let _send_res = Client::new().request(send_req).await?;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let (_, mut get_res_body) = Client::new().request(get_req).await?.into_parts();
+    assert_eq!(get_res_body.next().await.unwrap()?, "hello");
+    abort_tx.send(()).unwrap();
+    // Receiver should get an error instead of the end of the body
+    assert!(get_res_body.next().await.unwrap().is_err());
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should abort the transfer when the multipart body is longer than its Content-Length")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res_future = Client::new().request(get_req);
+
+    let send_body = "--BOUNDARY\r\nContent-Disposition: form-data; name=\"input_data\"\r\nContent-Length: 3\r\n\r\nhello\r\n--BOUNDARY--\r\n";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "multipart/form-data; boundary=BOUNDARY")
+        .uri(uri.clone())
+        .body(hyper::Body::from(send_body))?;
+    let send_res = Client::new().request(send_req).await?;
+
+    // Receiver should not get the body as complete
+    let receiving_failed = match get_res_future.await {
+        Ok(get_res) => hyper::body::to_bytes(get_res.into_body()).await.is_err(),
+        Err(_) => true,
+    };
+    assert!(receiving_failed);
+
+    let send_res_string = String::from_utf8(read_all_body(send_res.into_body()).await)?;
+    assert!(
+        send_res_string.ends_with("[ERROR] 5 bytes were received, but 3 bytes were declared.\n")
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should abort the transfer when the body is shorter than its Content-Range")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res_future = Client::new().request(get_req);
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Range", "bytes 0-9/10")
+        .uri(uri.clone())
+        .body(hyper::Body::from("hello"))?;
+    let send_res = Client::new().request(send_req).await?;
+
+    // Receiver should not get the body as complete
+    let receiving_failed = match get_res_future.await {
+        Ok(get_res) => hyper::body::to_bytes(get_res.into_body()).await.is_err(),
+        Err(_) => true,
+    };
+    assert!(receiving_failed);
+
+    let send_res_string = String::from_utf8(read_all_body(send_res.into_body()).await)?;
+    assert!(
+        send_res_string.ends_with("[ERROR] 5 bytes were received, but 10 bytes were declared.\n")
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should pass X-Piping and attach Access-Control-Expose-Headers: X-Piping when sending with X-Piping")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+
+    let send_body_str = "this is a content";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "text/plain")
+        .header("X-Piping", "mymetadata")
+        .uri(uri.clone())
+        .body(hyper::Body::from(send_body_str))?;
+
+    let client = Client::new();
+    let send_res = client.request(send_req).await?;
+    let (send_res_parts, _send_res_body) = send_res.into_parts();
+    assert_eq!(send_res_parts.status, http::StatusCode::OK);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let client = Client::new();
+    let (parts, body) = client.request(get_req).await?.into_parts();
+
+    let all_bytes: Vec<u8> = read_all_body(body).await;
+
+    let expect = send_body_str.to_owned().into_bytes();
+    assert_eq!(all_bytes, expect);
+
+    assert_eq!(
+        get_header_value(&parts.headers, "content-type"),
+        Some("text/plain")
+    );
+    assert_eq!(
+        get_header_value(&parts.headers, "content-length"),
+        Some(send_body_str.len().to_string().as_str())
+    );
+    assert_eq!(
+        get_header_value(&parts.headers, "content-disposition"),
+        None
+    );
+    assert_eq!(
+        get_header_value(&parts.headers, "access-control-allow-origin"),
+        Some("*")
+    );
+    assert_eq!(
+        get_header_value(&parts.headers, "x-robots-tag"),
+        Some("none")
+    );
+    assert_eq!(
+        get_header_value(&parts.headers, "access-control-expose-headers"),
+        Some("X-Piping"),
+    );
+    assert_eq!(
+        get_header_value(&parts.headers, "X-Piping"),
+        Some("mymetadata"),
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should pass multiple X-Piping")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+
+    let send_body_str = "this is a content";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "text/plain")
+        .header("X-Piping", "mymetadata1")
+        .header("X-Piping", "mymetadata2")
+        .header("X-Piping", "mymetadata3")
+        .uri(uri.clone())
+        .body(hyper::Body::from(send_body_str))?;
+
+    let client = Client::new();
+    let send_res = client.request(send_req).await?;
+    let (send_res_parts, _send_res_body) = send_res.into_parts();
+    assert_eq!(send_res_parts.status, http::StatusCode::OK);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let client = Client::new();
+    let (parts, body) = client.request(get_req).await?.into_parts();
+
+    let all_bytes: Vec<u8> = read_all_body(body).await;
+
+    let expect = send_body_str.to_owned().into_bytes();
+    assert_eq!(all_bytes, expect);
+
+    assert_eq!(
+        get_header_value(&parts.headers, "access-control-expose-headers"),
+        Some("X-Piping"),
+    );
+    assert_eq!(
+        parts
+            .headers
+            .get_all("X-Piping")
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec!["mymetadata1", "mymetadata2", "mymetadata3"],
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should handle multiple receivers specified by n")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath?n=2", serve.addr).parse::<http::Uri>()?;
+
+    let mut get_res_rxs = Vec::new();
+    for _ in 0..2 {
+        let (get_res_tx, get_res_rx) = oneshot::channel();
+        tokio::spawn({
+            let uri = uri.clone();
+            async {
+                let get_req = hyper::Request::builder()
+                    .method(hyper::Method::GET)
+                    .uri(uri)
+                    .body(hyper::Body::empty())?;
+                let client = Client::new();
+                let get_res = client.request(get_req).await?;
+                get_res_tx.send(get_res).unwrap();
+                Ok::<_, BoxError>(())
+            }
+        });
+        get_res_rxs.push(get_res_rx);
+    }
+
+    let send_body_str = "this is a content";
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "text/plain")
+        .uri(uri.clone())
+        .body(hyper::Body::from(send_body_str))?;
+
+    let client = Client::new();
+    let send_res = client.request(send_req).await?;
+    let (send_res_parts, send_res_body) = send_res.into_parts();
+    assert_eq!(send_res_parts.status, http::StatusCode::OK);
+
+    for get_res_rx in get_res_rxs {
+        let (parts, body) = get_res_rx.await?.into_parts();
+        let all_bytes: Vec<u8> = read_all_body(body).await;
+        assert_eq!(all_bytes, send_body_str.to_owned().into_bytes());
+        assert_eq!(
+            get_header_value(&parts.headers, "content-length"),
+            Some(send_body_str.len().to_string().as_str())
+        );
+    }
+
+    let send_res_string = String::from_utf8(read_all_body(send_res_body).await)?;
+    assert!(send_res_string.contains("[INFO] 2 receiver(s) has/have been connected."));
+    assert!(send_res_string.contains("[INFO] Start sending to 2 receiver(s)..."));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should reject a receiver with a different n from sender's one")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath?n=2", serve.addr).parse::<http::Uri>()?)
+        .body(hyper::Body::from("this is a content"))?;
+    let client = Client::new();
+    let send_res = client.request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath?n=3", serve.addr).parse::<http::Uri>()?)
+        .body(hyper::Body::empty())?;
+    let (parts, body) = client.request(get_req).await?.into_parts();
+    assert_eq!(parts.status, http::StatusCode::BAD_REQUEST);
+    let body_string = String::from_utf8(read_all_body(body).await)?;
+    assert_eq!(
+        body_string,
+        "[ERROR] The number of receivers should be 2 but 3.\n"
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should resume an interrupted upload by Content-Range")]
+async fn f() -> Result<(), BoxError> {
+    use futures::stream::StreamExt;
+
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+
+    let (get_res_tx, get_res_rx) = oneshot::channel();
+    tokio::spawn({
+        let uri = uri.clone();
+        async {
+            let get_req = hyper::Request::builder()
+                .method(hyper::Method::GET)
+                .uri(uri)
+                .body(hyper::Body::empty())?;
+            let client = Client::new();
+            let get_res = client.request(get_req).await?;
+            get_res_tx.send(get_res).unwrap();
+            Ok::<_, BoxError>(())
+        }
+    });
+
+    // Send the first half and abort
+    let (abort_tx, abort_rx) = oneshot::channel::<()>();
+    let first_body = futures::stream::iter(vec![Ok::<_, BoxError>("hello")]).chain(
+        futures::stream::once(async {
+            let _ = abort_rx.await;
+            Err::<&str, BoxError>("aborted".into())
+        }),
+    );
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "text/plain")
+        .header("Content-Range", "bytes 0-9/10")
+        .uri(uri.clone())
+        .body(hyper::Body::wrap_stream(first_body))?;
+    let client = Client::new();
+    let send_res = client.request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    let (parts, mut body) = get_res_rx.await?.into_parts();
+    assert_eq!(
+        get_header_value(&parts.headers, "content-length"),
+        Some("10")
+    );
+    let first_bytes = body.next().await.unwrap()?;
+    assert_eq!(first_bytes, "hello");
+    abort_tx.send(()).unwrap();
+
+    // Non-contiguous range should be rejected
+    let mut resume_res;
+    loop {
+        let resume_req = hyper::Request::builder()
+            .method(hyper::Method::PUT)
+            .header("Content-Range", "bytes 6-9/10")
+            .uri(uri.clone())
+            .body(hyper::Body::from("orld"))?;
+        resume_res = client.request(resume_req).await?;
+        let body_string = String::from_utf8(read_all_body(resume_res.into_body()).await)?;
+        // Wait for the server to detect the interruption
+        if body_string.contains("No interrupted transfer") {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            continue;
+        }
+        assert_eq!(
+            body_string,
+            "[ERROR] Content-Range should start at 5, but 6.\n"
+        );
+        break;
+    }
+
+    let resume_req = hyper::Request::builder()
+        .method(hyper::Method::PUT)
+        .header("Content-Range", "bytes 5-9/10")
+        .uri(uri.clone())
+        .body(hyper::Body::from("world"))?;
+    resume_res = client.request(resume_req).await?;
+    assert_eq!(resume_res.status(), http::StatusCode::OK);
+
+    let rest_bytes = read_all_body(body).await;
+    assert_eq!(rest_bytes, b"world".to_vec());
+    let resume_res_string = String::from_utf8(read_all_body(resume_res.into_body()).await)?;
+    assert!(resume_res_string.contains("[INFO] Sent successfully!"));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should resume a download by Range within the replay window")]
+async fn f() -> Result<(), BoxError> {
+    use futures::stream::StreamExt;
+
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        replay_window_size: 1024,
+        ..PipingServerConfig::default()
+    })
+    .await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+
+    let (mut send_body_sender, send_body) = hyper::Body::channel();
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "text/plain")
+        .header("Content-Length", "11")
+        .uri(uri.clone())
+        .body(send_body)?;
+    let client = Client::new();
+    let send_res = client.request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let (_, mut body) = Client::new().request(get_req).await?.into_parts();
+    send_body_sender.send_data("hello".into()).await?;
+    assert_eq!(body.next().await.unwrap()?, "hello");
+    // Disconnect the receiver
+    drop(body);
+    send_body_sender.send_data("world".into()).await?;
+
+    let res = loop {
+        let get_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .header("Range", "bytes=3-")
+            .uri(uri.clone())
+            .body(hyper::Body::empty())?;
+        let res = client.request(get_req).await?;
+        // Wait for the server to detect the disconnection
+        if res.status() == http::StatusCode::BAD_REQUEST {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            continue;
+        }
+        break res;
+    };
+    assert_eq!(res.status(), http::StatusCode::PARTIAL_CONTENT);
+    assert_eq!(
+        get_header_value(res.headers(), "content-type"),
+        Some("text/plain")
+    );
+    assert_eq!(
+        get_header_value(res.headers(), "content-range"),
+        Some("bytes 3-10/11")
+    );
+    send_body_sender.send_data("!".into()).await?;
+    drop(send_body_sender);
+    let all_bytes = read_all_body(res.into_body()).await;
+    assert_eq!(all_bytes, b"loworld!".to_vec());
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should refuse Range resumption when the sender's length is unknown")]
+async fn f() -> Result<(), BoxError> {
+    use futures::stream::StreamExt;
+
+    let serve: Serve = serve_with_config(PipingServerConfig {
+        replay_window_size: 1024,
+        ..PipingServerConfig::default()
+    })
+    .await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+
+    let (mut send_body_sender, send_body) = hyper::Body::channel();
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "text/plain")
+        .uri(uri.clone())
+        .body(send_body)?;
+    let client = Client::new();
+    let send_res = client.request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let (_, mut body) = Client::new().request(get_req).await?.into_parts();
+    send_body_sender.send_data("hello".into()).await?;
+    assert_eq!(body.next().await.unwrap()?, "hello");
+    // Disconnect the receiver
+    drop(body);
+    send_body_sender.send_data("world".into()).await?;
+
+    let res = loop {
+        let get_req = hyper::Request::builder()
+            .method(hyper::Method::GET)
+            .header("Range", "bytes=3-")
+            .uri(uri.clone())
+            .body(hyper::Body::empty())?;
+        let res = client.request(get_req).await?;
+        // Wait for the server to detect the disconnection
+        if res.status() == http::StatusCode::BAD_REQUEST {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            continue;
+        }
+        break res;
+    };
+    assert_eq!(res.status(), http::StatusCode::RANGE_NOT_SATISFIABLE);
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should relay each request body to the other's response in duplex mode")]
+async fn f() -> Result<(), BoxError> {
+    use futures::stream::StreamExt;
+
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath?duplex", serve.addr).parse::<http::Uri>()?;
+
+    let (mut body_sender1, body1) = hyper::Body::channel();
+    let (res_tx1, res_rx1) = oneshot::channel();
+    tokio::spawn({
+        let uri = uri.clone();
+        async {
+            let req = hyper::Request::builder()
+                .method(hyper::Method::POST)
+                .header("Content-Type", "text/plain")
+                .uri(uri)
+                .body(body1)?;
+            let res = Client::new().request(req).await?;
+            res_tx1.send(res).unwrap();
+            Ok::<_, BoxError>(())
+        }
+    });
+
+    let (mut body_sender2, body2) = hyper::Body::channel();
+    let req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Content-Type", "application/octet-stream")
+        .uri(uri.clone())
+        .body(body2)?;
+    let (parts2, mut res_body2) = Client::new().request(req).await?.into_parts();
+    let (parts1, mut res_body1) = res_rx1.await?.into_parts();
+    assert_eq!(
+        get_header_value(&parts1.headers, "content-type"),
+        Some("application/octet-stream")
+    );
+    assert_eq!(
+        get_header_value(&parts2.headers, "content-type"),
+        Some("text/plain")
+    );
+
+    // Interactive exchanges over the same pair of requests
+    body_sender1.send_data("ping".into()).await?;
+    assert_eq!(res_body2.next().await.unwrap()?, "ping");
+    body_sender2.send_data("pong".into()).await?;
+    assert_eq!(res_body1.next().await.unwrap()?, "pong");
+
+    drop(body_sender1);
+    drop(body_sender2);
+    assert!(res_body1.next().await.is_none());
+    assert!(res_body2.next().await.is_none());
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should transfer from a WebSocket sender to a WebSocket receiver")]
+async fn f() -> Result<(), BoxError> {
+    use futures::sink::SinkExt;
+    use futures::stream::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let serve: Serve = serve().await;
+
+    let (mut receiver_websocket, _) = tokio_tungstenite::client_async(
+        format!("ws://{}/mypath", serve.addr),
+        tokio::net::TcpStream::connect(serve.addr).await?,
+    )
+    .await?;
+    let (mut sender_websocket, _) = tokio_tungstenite::client_async(
+        format!("ws://{}/mypath?role=sender", serve.addr),
+        tokio::net::TcpStream::connect(serve.addr).await?,
+    )
+    .await?;
+
+    sender_websocket
+        .send(Message::Binary(b"hello".to_vec()))
+        .await?;
+    assert_eq!(
+        receiver_websocket.next().await.unwrap()?,
+        Message::Binary(b"hello".to_vec())
+    );
+    // Finish sending by an empty binary frame
+    sender_websocket.send(Message::Binary(vec![])).await?;
+    assert!(matches!(
+        receiver_websocket.next().await.unwrap()?,
+        Message::Close(_)
+    ));
+
+    // [INFO] messages are delivered as text frames
+    let mut sender_messages = String::new();
+    while let Some(Ok(Message::Text(text))) = sender_websocket.next().await {
+        sender_messages += &text;
+    }
+    assert!(sender_messages.contains("[INFO] 1 receiver(s) has/have been connected."));
+    assert!(sender_messages.contains("[INFO] Sent successfully!"));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should transfer from an HTTP sender to a WebSocket receiver")]
+async fn f() -> Result<(), BoxError> {
+    use futures::stream::StreamExt;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let serve: Serve = serve().await;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?)
+        .body(hyper::Body::from("this is a content"))?;
+    let send_res = Client::new().request(send_req).await?;
+    assert_eq!(send_res.status(), http::StatusCode::OK);
+
+    let (mut receiver_websocket, _) = tokio_tungstenite::client_async(
+        format!("ws://{}/mypath", serve.addr),
+        tokio::net::TcpStream::connect(serve.addr).await?,
+    )
+    .await?;
+    let mut all_bytes: Vec<u8> = Vec::new();
+    while let Some(Ok(Message::Binary(mut data))) = receiver_websocket.next().await {
+        all_bytes.append(&mut data);
+    }
+    assert_eq!(all_bytes, b"this is a content".to_vec());
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should close a WebSocket receiver with 1011 when the sender fails")]
+async fn f() -> Result<(), BoxError> {
+    use futures::stream::StreamExt;
+    use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+    use tokio_tungstenite::tungstenite::Message;
+
+    let serve: Serve = serve().await;
+
+    let (mut receiver_websocket, _) = tokio_tungstenite::client_async(
+        format!("ws://{}/mypath", serve.addr),
+        tokio::net::TcpStream::connect(serve.addr).await?,
+    )
+    .await?;
+
+    let (mut send_body_sender, send_body) = hyper::Body::channel();
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?)
+        .body(send_body)?;
+    let send_res_future = tokio::spawn(Client::new().request(send_req));
+    send_body_sender.send_data("hello".into()).await?;
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    // The sender leaves in the middle of the transfer
+    send_body_sender.abort();
+    let _ = send_res_future.await?;
+
+    let mut all_bytes: Vec<u8> = Vec::new();
+    let close_frame = loop {
+        match receiver_websocket.next().await {
+            Some(Ok(Message::Binary(mut data))) => all_bytes.append(&mut data),
+            Some(Ok(Message::Close(close_frame))) => break close_frame,
+            message => panic!("unexpected message: {:?}", message),
+        }
+    };
+    assert_eq!(all_bytes, b"hello".to_vec());
+    assert_eq!(
+        close_frame.map(|close_frame| close_frame.code),
+        Some(CloseCode::Error)
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should free the path when a waiting WebSocket receiver closes")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+    let (mut receiver_websocket, _) = tokio_tungstenite::client_async(
+        format!("ws://{}/mypath", serve.addr),
+        tokio::net::TcpStream::connect(serve.addr).await?,
+    )
+    .await?;
+    receiver_websocket.close(None).await?;
+    drop(receiver_websocket);
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let get_res_join_handle = tokio::spawn(Client::new().request(get_req));
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("this is a content"))?;
+    let _send_res = Client::new().request(send_req).await?;
+    let get_res = get_res_join_handle.await??;
+    assert_eq!(get_res.status(), http::StatusCode::OK);
+    assert_eq!(
+        read_all_body(get_res.into_body()).await,
+        "this is a content".as_bytes().to_vec()
+    );
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should send events to sender with Accept: text/event-stream")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?;
+
+    let (mut send_body_sender, send_body) = hyper::Body::channel();
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .header("Accept", "text/event-stream")
+        .uri(uri.clone())
+        .body(send_body)?;
+    let client = Client::new();
+    let (send_res_parts, send_res_body) = client.request(send_req).await?.into_parts();
+    assert_eq!(
+        get_header_value(&send_res_parts.headers, "content-type"),
+        Some("text/event-stream")
+    );
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(uri.clone())
+        .body(hyper::Body::empty())?;
+    let (_, get_res_body) = client.request(get_req).await?.into_parts();
+    send_body_sender.send_data("hello".into()).await?;
+    // Wait for a progress event
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    send_body_sender.send_data("world".into()).await?;
+    drop(send_body_sender);
+    assert_eq!(read_all_body(get_res_body).await, b"helloworld".to_vec());
+
+    let events = String::from_utf8(read_all_body(send_res_body).await)?;
+    assert!(events.starts_with(
+        "event: waiting\ndata: {\"event\":\"waiting\",\"n_receivers\":1,\"path\":\"/mypath\","
+    ));
+    assert!(events.contains("event: receiver_connected\n"));
+    assert!(events.contains("event: start\n"));
+    assert!(events.contains("event: progress\ndata: {\"event\":\"progress\",\"n_bytes\":5,"));
+    assert!(events.contains("event: finished\ndata: {\"event\":\"finished\",\"n_bytes\":10,"));
+    assert!(events.ends_with("}\n\n"));
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should choose sender's response format by media types in Accept")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let client = Client::new();
+    for (i, (accept, content_type)) in [
+        // Default of axios
+        ("application/json, text/plain, */*", "text/plain"),
+        ("application/x-ndjson", "application/x-ndjson"),
+        ("text/plain, application/x-ndjson;q=0", "text/plain"),
+        ("text/event-stream;q=0.5", "text/event-stream"),
+    ]
+    .iter()
+    .enumerate()
+    {
+        let send_req = hyper::Request::builder()
+            .method(hyper::Method::POST)
+            .uri(format!("http://{}/mypath{}", serve.addr, i).parse::<http::Uri>()?)
+            .header("Accept", *accept)
+            .body(hyper::Body::from("this is a content"))?;
+        let send_res = client.request(send_req).await?;
+        assert_eq!(
+            get_header_value(send_res.headers(), "content-type"),
+            Some(*content_type),
+            "Accept: {}",
+            accept
+        );
+    }
+
+    serve.shutdown_tx.send(()).expect("shutdown failed");
+    Ok(())
+}
+
+#[it("should send NDJSON events to sender with ?format=json")]
+async fn f() -> Result<(), BoxError> {
+    let serve: Serve = serve().await;
+
+    let uri = format!("http://{}/mypath?format=json", serve.addr).parse::<http::Uri>()?;
+
+    let send_req = hyper::Request::builder()
+        .method(hyper::Method::POST)
+        .uri(uri.clone())
+        .body(hyper::Body::from("this is a content"))?;
+    let client = Client::new();
+    let (send_res_parts, send_res_body) = client.request(send_req).await?.into_parts();
+    assert_eq!(
+        get_header_value(&send_res_parts.headers, "content-type"),
+        Some("application/x-ndjson")
+    );
+
+    let get_req = hyper::Request::builder()
+        .method(hyper::Method::GET)
+        .uri(format!("http://{}/mypath", serve.addr).parse::<http::Uri>()?)
+        .body(hyper::Body::empty())?;
+    let (_, get_res_body) = client.request(get_req).await?.into_parts();
+    assert_eq!(
+        read_all_body(get_res_body).await,
+        "this is a content".as_bytes().to_vec()
+    );
+
+    let lines = String::from_utf8(read_all_body(send_res_body).await)?;
+    let events = lines
+        .lines()
+        .map(serde_json::from_str::<serde_json::Value>)
+        .collect::<Result<Vec<_>, _>>()?;
+    let event_names: Vec<&str> = events
+        .iter()
+        .map(|event| event["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(event_names.first(), Some(&"waiting"));
+    assert!(event_names.contains(&"receiver_connected"));
+    assert!(event_names.contains(&"start"));
+    assert_eq!(event_names.last(), Some(&"finished"));
+    for event in &events {
+        assert_eq!(event["path"], "/mypath");
+        assert!(event["timestamp"].is_u64());
+    }
+    assert_eq!(events.last().unwrap()["n_bytes"], 17);
+
     serve.shutdown_tx.send(()).expect("shutdown failed");
     Ok(())
 }